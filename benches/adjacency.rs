@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use wv::core::{EntityId, Weave};
+use wv::shape::hoist;
+use wv::traverse::{arrows_in, arrows_out, arrows_out_iter, down, down_iter, next_n, prev_n, up_n};
+
+const KNOTS: usize = 100_000;
+
+/*
+    A ring of knots where every knot also points at the next ten, all
+    hoisted under one environment knot
+ */
+fn build_weave() -> (Weave, usize, Vec<usize>) {
+    let mut wv = Weave::builder().capacity(KNOTS * 16).build();
+    let knots = (0..KNOTS).map(|_| wv.new_knot()).collect::<Vec<_>>();
+    for (i, knot) in knots.iter().enumerate() {
+        for step in 1..=10 {
+            wv.new_arrow(*knot, knots[(i + step) % KNOTS]);
+        }
+    }
+
+    let env = wv.new_knot();
+    hoist(&mut wv, env, &knots);
+    (wv, env, knots)
+}
+
+/*
+    The adjacency storage from before the sorted per-slot vectors: a hash
+    set of dependents per entity, copied and sorted on every lookup, with
+    the moves built on it the way they used to be. The `/hashset` benches
+    run on this so the two can be compared on the same weave.
+ */
+#[derive(Default)]
+struct HashSetAdjacency {
+    source_ids: HashMap<EntityId, HashSet<EntityId>>,
+    target_ids: HashMap<EntityId, HashSet<EntityId>>,
+}
+
+impl HashSetAdjacency {
+    fn from_weave(wv: &Weave) -> Self {
+        let mut adjacency = Self::default();
+        for e in wv.entities() {
+            adjacency.source_ids.entry(wv.src(e)).or_default().insert(e);
+            adjacency.target_ids.entry(wv.tgt(e)).or_default().insert(e);
+        }
+        adjacency
+    }
+
+    fn for_source(&self, src: EntityId) -> Vec<EntityId> {
+        let mut v = self.source_ids.get(&src).cloned().unwrap_or_default().into_iter().collect::<Vec<_>>();
+        v.sort();
+        v
+    }
+
+    fn for_target(&self, tgt: EntityId) -> Vec<EntityId> {
+        let mut v = self.target_ids.get(&tgt).cloned().unwrap_or_default().into_iter().collect::<Vec<_>>();
+        v.sort();
+        v
+    }
+
+    fn external_dependents(&self, id: EntityId) -> Vec<EntityId> {
+        let mut v = self.for_source(id);
+        v.extend_from_slice(&self.for_target(id));
+        v.sort();
+        v.dedup();
+        v.retain(|&e| e != id);
+        v
+    }
+
+    fn arrows_out(&self, wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
+        let mut h = HashSet::new();
+        for i in it {
+            h.extend(self.for_source(*i).into_iter().filter(|&e| e != *i && wv.is_arrow(e)));
+        }
+        h.into_iter().collect()
+    }
+
+    fn arrows_in(&self, wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
+        let mut h = HashSet::new();
+        for i in it {
+            h.extend(self.for_target(*i).into_iter().filter(|&e| e != *i && wv.is_arrow(e)));
+        }
+        h.into_iter().collect()
+    }
+
+    fn next_n(&self, wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
+        let mut c = it.iter().flat_map(|&i| {
+            let deps = self.external_dependents(i).into_iter().collect::<HashSet<_>>();
+            let mut ts = deps.into_iter().map(|d| wv.tgt(d)).collect::<Vec<_>>();
+            ts.sort();
+            ts.dedup();
+            ts.retain(|&e| e != i);
+            ts
+        }).collect::<Vec<_>>();
+        c.sort();
+        c.dedup();
+        c
+    }
+
+    fn up_n(&self, wv: &Weave, its: &[EntityId]) -> Vec<EntityId> {
+        let mut c = its.iter().flat_map(|&i| {
+            let marks = self.for_target(i).into_iter().filter(|&e| e != i && wv.is_mark(e)).collect::<HashSet<_>>();
+            let arrows = self.arrows_in(wv, &marks.into_iter().collect::<Vec<_>>());
+            arrows.into_iter().map(|a| wv.src(wv.src(a))).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        c.sort();
+        c.dedup();
+        c
+    }
+
+    fn down(&self, wv: &Weave, it: EntityId) -> Vec<EntityId> {
+        let tethers = self.for_source(it).into_iter().filter(|&e| e != it && wv.is_tether(e)).collect::<HashSet<_>>();
+        let arrows = self.arrows_out(wv, &tethers.into_iter().collect::<Vec<_>>());
+        arrows.into_iter().map(|a| wv.tgt(wv.tgt(a))).collect()
+    }
+}
+
+/*
+    Builds just the dependents index the way the weave does now, one
+    sorted vector per slot, to set against building the hash sets
+ */
+fn sorted_vec_adjacency(wv: &Weave) -> (Vec<Vec<EntityId>>, Vec<Vec<EntityId>>) {
+    let slots = wv.entities().max().map_or(0, |e| e + 1);
+    let (mut sources, mut targets) = (vec![Vec::new(); slots], vec![Vec::new(); slots]);
+    for e in wv.entities() {
+        for (dependents, end) in [ (&mut sources, wv.src(e)), (&mut targets, wv.tgt(e)) ] {
+            let dependents: &mut Vec<EntityId> = &mut dependents[end];
+            if let Err(at) = dependents.binary_search(&e) {
+                dependents.insert(at, e);
+            }
+        }
+    }
+    (sources, targets)
+}
+
+fn traversal(c: &mut Criterion) {
+    let (wv, env, knots) = build_weave();
+    let sample = knots.iter().step_by(100).copied().collect::<Vec<_>>();
+
+    c.bench_function("arrows_out/100k", |b| b.iter(|| arrows_out(&wv, black_box(&sample))));
+    c.bench_function("arrows_in/100k", |b| b.iter(|| arrows_in(&wv, black_box(&sample))));
+    c.bench_function("next_n/100k", |b| b.iter(|| next_n(&wv, black_box(&sample))));
+    c.bench_function("prev_n/100k", |b| b.iter(|| prev_n(&wv, black_box(&sample))));
+    c.bench_function("up_n/100k", |b| b.iter(|| up_n(&wv, black_box(&sample))));
+    c.bench_function("down/100k", |b| b.iter(|| down(&wv, black_box(env))));
+    c.bench_function("arrows_out_iter/100k", |b| b.iter(|| arrows_out_iter(&wv, black_box(&sample).iter().copied()).count()));
+    c.bench_function("down_iter/100k", |b| b.iter(|| down_iter(&wv, black_box(env)).count()));
+
+    let old = HashSetAdjacency::from_weave(&wv);
+    c.bench_function("arrows_out/100k/hashset", |b| b.iter(|| old.arrows_out(&wv, black_box(&sample))));
+    c.bench_function("arrows_in/100k/hashset", |b| b.iter(|| old.arrows_in(&wv, black_box(&sample))));
+    c.bench_function("next_n/100k/hashset", |b| b.iter(|| old.next_n(&wv, black_box(&sample))));
+    c.bench_function("up_n/100k/hashset", |b| b.iter(|| old.up_n(&wv, black_box(&sample))));
+    c.bench_function("down/100k/hashset", |b| b.iter(|| old.down(&wv, black_box(env))));
+}
+
+fn construction(c: &mut Criterion) {
+    c.bench_function("build/100k", |b| b.iter(build_weave));
+
+    // only the dependents index differs between the two storages, so
+    // that's what is built here, from the same finished weave
+    let (wv, _, _) = build_weave();
+    c.bench_function("index/100k/sorted", |b| b.iter(|| sorted_vec_adjacency(black_box(&wv))));
+    c.bench_function("index/100k/hashset", |b| b.iter(|| HashSetAdjacency::from_weave(black_box(&wv))));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = traversal, construction
+}
+criterion_main!(benches);
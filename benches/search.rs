@@ -0,0 +1,77 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use wv::core::Weave;
+use wv::search::{find_all_exhaustive, find_all_with, find_one_exhaustive, find_one_with, MatchOptions};
+use wv::shape::hoist;
+
+/*
+    A ring of knots where every knot also points at the next `fanout`,
+    hoisted under one environment knot
+ */
+fn build_target(wv: &mut Weave, knots: usize, fanout: usize) -> usize {
+    let ring = (0..knots).map(|_| wv.new_knot()).collect::<Vec<_>>();
+    for (i, knot) in ring.iter().enumerate() {
+        for step in 1..=fanout {
+            wv.new_arrow(*knot, ring[(i + step) % knots]);
+        }
+    }
+
+    let env = wv.new_knot();
+    hoist(wv, env, &ring);
+    env
+}
+
+/*
+    A chain of knots, closed into a cycle if asked to, hoisted under one
+    environment knot
+ */
+fn build_pattern(wv: &mut Weave, knots: usize, cycle: bool) -> usize {
+    let chain = (0..knots).map(|_| wv.new_knot()).collect::<Vec<_>>();
+    for pair in chain.windows(2) {
+        wv.new_arrow(pair[0], pair[1]);
+    }
+    if cycle {
+        wv.new_arrow(chain[knots - 1], chain[0]);
+    }
+
+    let env = wv.new_knot();
+    hoist(wv, env, &chain);
+    env
+}
+
+fn small(c: &mut Criterion) {
+    let mut wv = Weave::new();
+    let target = build_target(&mut wv, 8, 2);
+    let path = build_pattern(&mut wv, 3, false);
+    let cycle = build_pattern(&mut wv, 3, true);
+    let options = MatchOptions::default();
+
+    c.bench_function("find_one/path3/matcher", |b| b.iter(|| find_one_with(&wv, black_box(path), target, options)));
+    c.bench_function("find_one/path3/exhaustive", |b| b.iter(|| find_one_exhaustive(&wv, black_box(path), target, options)));
+    c.bench_function("find_all/path3/matcher", |b| b.iter(|| find_all_with(&wv, black_box(path), target, options)));
+    c.bench_function("find_all/path3/exhaustive", |b| b.iter(|| find_all_exhaustive(&wv, black_box(path), target, options)));
+    c.bench_function("find_all/cycle3/matcher", |b| b.iter(|| find_all_with(&wv, black_box(cycle), target, options)));
+    c.bench_function("find_all/cycle3/exhaustive", |b| b.iter(|| find_all_exhaustive(&wv, black_box(cycle), target, options)));
+}
+
+fn large(c: &mut Criterion) {
+    let mut wv = Weave::new();
+    let target = build_target(&mut wv, 100, 3);
+    let path = build_pattern(&mut wv, 40, false);
+    let options = MatchOptions::default();
+
+    // a 40-cycle only closes on its last arrow, so on a ring much longer
+    // than 40 it's a search for something that mostly isn't there;
+    // matching it against a ring of its own length keeps it meaningful
+    let ring = build_target(&mut wv, 40, 3);
+    let cycle = build_pattern(&mut wv, 40, true);
+
+    c.bench_function("find_one/path40/matcher", |b| b.iter(|| find_one_with(&wv, black_box(path), target, options)));
+    c.bench_function("find_one/cycle40/matcher", |b| b.iter(|| find_one_with(&wv, black_box(cycle), ring, options)));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = small, large
+}
+criterion_main!(benches);
@@ -6,19 +6,54 @@
 #pragma comment(lib, "userenv.lib")
 #pragma comment(lib, "ntdll.lib")
 
-enum class Datatype {
+static const uint64_t FORMAT_VERSION = 2;
+
+static const uint64_t LEGACY_FORMAT_VERSION = 1;
+
+enum class RefPolicy {
+  Nullify,
+  Cascade,
+  Restrict,
+};
+
+enum class WvDatatypeKind {
   Entity,
   Int,
   Float,
   Bool,
   String,
+  Bytes,
+  List,
+  Optional,
+  Enum,
+  Vec2,
+  Vec3,
+  Vec4,
+};
+
+enum class WvStopReason {
+  None,
+  Count,
+  Steps,
+  Timeout,
+  Cancelled,
 };
 
 struct Weave;
 
+struct WvSearchCursor;
+
+struct WvDatatype {
+  WvDatatypeKind kind;
+  const WvDatatype *item;
+  const char *name;
+};
+
 struct WvDataField {
   const char *name;
-  Datatype datatype;
+  WvDatatype datatype;
+  const void *default_;
+  RefPolicy on_delete;
 };
 
 using EntityId = size_t;
@@ -28,6 +63,17 @@ struct WvEntityArray {
   const size_t *ptr;
 };
 
+struct WvMatchOptions {
+  bool injective;
+  bool induced;
+};
+
+struct WvMatchLimits {
+  size_t max_count;
+  size_t max_steps;
+  uint64_t timeout_ms;
+};
+
 struct WvByteArray {
   size_t len;
   const uint8_t *ptr;
@@ -38,26 +84,58 @@ extern "C" {
 
 extern const size_t NIL;
 
-void wv_add_component(Weave *wv, size_t entity, const char *name, const void *const *fields);
+bool wv_add_component(Weave *wv, size_t entity, const char *name, const void *const *fields);
+
+bool wv_add_component_named(Weave *wv,
+                            size_t entity,
+                            const char *name,
+                            size_t len,
+                            const char *const *field_names,
+                            const void *const *fields);
 
-void wv_change_ends(Weave *wv, size_t id, size_t src, size_t tgt);
+bool wv_change_ends(Weave *wv, size_t id, size_t src, size_t tgt);
 
-void wv_change_src(Weave *wv, size_t id, size_t src);
+bool wv_change_src(Weave *wv, size_t id, size_t src);
 
-void wv_change_tgt(Weave *wv, size_t id, size_t tgt);
+bool wv_change_tgt(Weave *wv, size_t id, size_t tgt);
 
 uint64_t wv_def_data(Weave *wv, const char *name, const WvDataField *datatype, size_t len);
 
-void wv_delete_cascade(Weave *wv, size_t *id);
+bool wv_def_enum(Weave *wv, const char *name, size_t len, const char *const *variants);
+
+bool wv_delete_cascade(Weave *wv, size_t *id);
 
-void wv_delete_orphan(Weave *wv, size_t *id);
+bool wv_delete_orphan(Weave *wv, size_t *id);
 
 EntityId wv_deserialize(Weave *wv, size_t len, const uint8_t *it);
 
+WvEntityArray wv_entities_with(const Weave *wv, size_t len, const char *const *names);
+
+WvEntityArray wv_entities_without(const Weave *wv, size_t len, const char *const *names);
+
+size_t wv_entity_generation(size_t id);
+
+size_t wv_entity_index(size_t id);
+
+void wv_free_data_field(WvDataField field);
+
 void wv_free_weave(Weave *weave);
 
 const void *wv_get_component_field(const Weave *wv, size_t entity, const char *name, size_t index);
 
+const void *wv_get_component_field_item(const Weave *wv,
+                                        size_t entity,
+                                        const char *name,
+                                        size_t index,
+                                        size_t item);
+
+size_t wv_get_component_field_len(const Weave *wv, size_t entity, const char *name, size_t index);
+
+const void *wv_get_component_field_named(const Weave *wv,
+                                         size_t entity,
+                                         const char *name,
+                                         const char *field_name);
+
 WvDataField wv_get_data_field(const Weave *wv, const char *name, size_t index);
 
 size_t wv_get_data_field_count(const Weave *wv, const char *name);
@@ -74,6 +152,8 @@ bool wv_is_mark(const Weave *wv, size_t id);
 
 bool wv_is_nil(const Weave *wv, size_t id);
 
+bool wv_is_stale(const Weave *wv, size_t id);
+
 bool wv_is_tether(const Weave *wv, size_t id);
 
 bool wv_is_valid(const Weave *wv, size_t id);
@@ -82,32 +162,68 @@ WvEntityArray wv_move__arrows(Weave *wv, size_t len, const size_t *it);
 
 WvEntityArray wv_move__arrows_in(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__arrows_in_into(const Weave *wv,
+                               size_t len,
+                               const size_t *it,
+                               size_t cap,
+                               size_t *out);
+
+size_t wv_move__arrows_into(const Weave *wv, size_t len, const size_t *it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__arrows_out(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__arrows_out_into(const Weave *wv,
+                                size_t len,
+                                const size_t *it,
+                                size_t cap,
+                                size_t *out);
+
 WvEntityArray wv_move__deps(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__deps_into(const Weave *wv, size_t len, const size_t *it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__down(Weave *wv, size_t it);
 
+size_t wv_move__down_into(const Weave *wv, size_t it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__down_n(Weave *wv, size_t len, const size_t *it);
 
 WvEntityArray wv_move__marks(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__marks_into(const Weave *wv, size_t len, const size_t *it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__next(Weave *wv, size_t it);
 
+size_t wv_move__next_into(const Weave *wv, size_t it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__next_n(Weave *wv, size_t len, const size_t *it);
 
 WvEntityArray wv_move__prev(Weave *wv, size_t it);
 
+size_t wv_move__prev_into(const Weave *wv, size_t it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__prev_n(Weave *wv, size_t len, const size_t *it);
 
 WvEntityArray wv_move__tethers(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__tethers_into(const Weave *wv,
+                             size_t len,
+                             const size_t *it,
+                             size_t cap,
+                             size_t *out);
+
 WvEntityArray wv_move__to_src(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__to_src_into(const Weave *wv, size_t len, const size_t *it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__to_tgt(Weave *wv, size_t len, const size_t *it);
 
+size_t wv_move__to_tgt_into(const Weave *wv, size_t len, const size_t *it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__up(Weave *wv, size_t it);
 
+size_t wv_move__up_into(const Weave *wv, size_t it, size_t cap, size_t *out);
+
 WvEntityArray wv_move__up_n(Weave *wv, size_t len, const size_t *it);
 
 size_t wv_new_arrow(Weave *wv, size_t src, size_t tgt);
@@ -120,27 +236,63 @@ size_t wv_new_tether(Weave *wv, size_t src);
 
 Weave *wv_new_weave();
 
-void wv_remove_component(Weave *wv, size_t entity, const char *name);
+WvEntityArray wv_query(const Weave *wv, size_t len, const size_t *it, const char *path);
+
+WvEntityArray wv_referrers(const Weave *wv, size_t entity);
+
+bool wv_remove_component(Weave *wv, size_t entity, const char *name);
 
 EntityId wv_replace__replace(Weave *wv,
                              size_t hoisted_pattern,
                              size_t hoisted_goal,
                              size_t hoisted_target);
 
+WvSearchCursor *wv_search__begin(const Weave *wv,
+                                 size_t hoisted_pattern,
+                                 size_t hoisted_target,
+                                 WvMatchOptions options,
+                                 WvMatchLimits limits,
+                                 bool (*cancel)(void*),
+                                 void *user_data);
+
+void wv_search__end(WvSearchCursor *cursor);
+
 WvEntityArray wv_search__find_all(const Weave *wv,
                                   size_t hoisted_pattern,
                                   size_t hoisted_target,
                                   size_t *size,
                                   size_t *count);
 
+WvEntityArray wv_search__find_all_with(const Weave *wv,
+                                       size_t hoisted_pattern,
+                                       size_t hoisted_target,
+                                       WvMatchOptions options,
+                                       size_t *size,
+                                       size_t *count);
+
 WvEntityArray wv_search__find_one(const Weave *wv,
                                   size_t hoisted_pattern,
                                   size_t hoisted_target,
                                   size_t *size,
                                   size_t *count);
 
+WvEntityArray wv_search__find_one_with(const Weave *wv,
+                                       size_t hoisted_pattern,
+                                       size_t hoisted_target,
+                                       WvMatchOptions options,
+                                       size_t *size,
+                                       size_t *count);
+
+bool wv_search__next(WvSearchCursor *cursor, WvEntityArray *solution);
+
+WvStopReason wv_search__stop_reason(const WvSearchCursor *cursor);
+
 WvByteArray wv_serialize(Weave *wv, size_t id);
 
+bool wv_set_component(Weave *wv, size_t entity, const char *name, const void *const *fields);
+
+void wv_set_strict_data(Weave *wv, bool strict);
+
 void wv_shape__connect(Weave *wv, size_t source, size_t len, const size_t *targets);
 
 void wv_shape__hoist(Weave *wv, size_t subject, size_t len, const size_t *objects);
@@ -157,4 +309,14 @@ size_t wv_src(const Weave *wv, size_t id);
 
 size_t wv_tgt(const Weave *wv, size_t id);
 
+bool wv_undef_data(Weave *wv, const char *name);
+
+bool wv_update_component_field(Weave *wv,
+                               size_t entity,
+                               const char *name,
+                               const char *field_name,
+                               const void *value);
+
+WvByteArray wv_upgrade(size_t len, const uint8_t *it);
+
 }  // extern "C"
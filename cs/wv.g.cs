@@ -43,13 +43,16 @@ namespace Weave
         internal static extern nuint wv_tgt(Weave* wv, nuint id);
 
         [DllImport(__DllName, EntryPoint = "wv_change_src", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_change_src(Weave* wv, nuint id, nuint src);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_change_src(Weave* wv, nuint id, nuint src);
 
         [DllImport(__DllName, EntryPoint = "wv_change_tgt", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_change_tgt(Weave* wv, nuint id, nuint tgt);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_change_tgt(Weave* wv, nuint id, nuint tgt);
 
         [DllImport(__DllName, EntryPoint = "wv_change_ends", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_change_ends(Weave* wv, nuint id, nuint src, nuint tgt);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_change_ends(Weave* wv, nuint id, nuint src, nuint tgt);
 
         [DllImport(__DllName, EntryPoint = "wv_is_knot", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         [return: MarshalAs(UnmanagedType.U1)]
@@ -75,15 +78,38 @@ namespace Weave
         [return: MarshalAs(UnmanagedType.U1)]
         internal static extern bool wv_is_nil(Weave* wv, nuint id);
 
+        [DllImport(__DllName, EntryPoint = "wv_is_stale", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_is_stale(Weave* wv, nuint id);
+
+        [DllImport(__DllName, EntryPoint = "wv_entity_index", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_entity_index(nuint id);
+
+        [DllImport(__DllName, EntryPoint = "wv_entity_generation", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_entity_generation(nuint id);
+
         [DllImport(__DllName, EntryPoint = "wv_delete_cascade", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_delete_cascade(Weave* wv, nuint* id);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_delete_cascade(Weave* wv, nuint* id);
 
         [DllImport(__DllName, EntryPoint = "wv_delete_orphan", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_delete_orphan(Weave* wv, nuint* id);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_delete_orphan(Weave* wv, nuint* id);
 
         [DllImport(__DllName, EntryPoint = "wv_def_data", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern ulong wv_def_data(Weave* wv, byte* name, WvDataField* datatype, nuint len);
 
+        [DllImport(__DllName, EntryPoint = "wv_def_enum", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_def_enum(Weave* wv, byte* name, nuint len, byte** variants);
+
+        [DllImport(__DllName, EntryPoint = "wv_undef_data", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_undef_data(Weave* wv, byte* name);
+
+        [DllImport(__DllName, EntryPoint = "wv_set_strict_data", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void wv_set_strict_data(Weave* wv, [MarshalAs(UnmanagedType.U1)] bool strict);
+
         [DllImport(__DllName, EntryPoint = "wv_get_data_id", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern ulong wv_get_data_id(Weave* wv, byte* name);
 
@@ -93,8 +119,24 @@ namespace Weave
         [DllImport(__DllName, EntryPoint = "wv_get_data_field", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern WvDataField wv_get_data_field(Weave* wv, byte* name, nuint index);
 
+        [DllImport(__DllName, EntryPoint = "wv_free_data_field", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void wv_free_data_field(WvDataField field);
+
         [DllImport(__DllName, EntryPoint = "wv_add_component", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_add_component(Weave* wv, nuint entity, byte* name, void** fields);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_add_component(Weave* wv, nuint entity, byte* name, void** fields);
+
+        [DllImport(__DllName, EntryPoint = "wv_add_component_named", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_add_component_named(Weave* wv, nuint entity, byte* name, nuint len, byte** field_names, void** fields);
+
+        [DllImport(__DllName, EntryPoint = "wv_set_component", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_set_component(Weave* wv, nuint entity, byte* name, void** fields);
+
+        [DllImport(__DllName, EntryPoint = "wv_update_component_field", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_update_component_field(Weave* wv, nuint entity, byte* name, byte* field_name, void* value);
 
         [DllImport(__DllName, EntryPoint = "wv_has_component", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         [return: MarshalAs(UnmanagedType.U1)]
@@ -103,8 +145,18 @@ namespace Weave
         [DllImport(__DllName, EntryPoint = "wv_get_component_field", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void* wv_get_component_field(Weave* wv, nuint entity, byte* name, nuint index);
 
+        [DllImport(__DllName, EntryPoint = "wv_get_component_field_len", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_get_component_field_len(Weave* wv, nuint entity, byte* name, nuint index);
+
+        [DllImport(__DllName, EntryPoint = "wv_get_component_field_item", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void* wv_get_component_field_item(Weave* wv, nuint entity, byte* name, nuint index, nuint item);
+
+        [DllImport(__DllName, EntryPoint = "wv_get_component_field_named", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void* wv_get_component_field_named(Weave* wv, nuint entity, byte* name, byte* field_name);
+
         [DllImport(__DllName, EntryPoint = "wv_remove_component", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
-        internal static extern void wv_remove_component(Weave* wv, nuint entity, byte* name);
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_remove_component(Weave* wv, nuint entity, byte* name);
 
         [DllImport(__DllName, EntryPoint = "wv_shape__parent", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void wv_shape__parent(Weave* wv, nuint root, nuint len, nuint* children);
@@ -124,6 +176,18 @@ namespace Weave
         [DllImport(__DllName, EntryPoint = "wv_shape__lower", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void wv_shape__lower(Weave* wv, nuint len, nuint* arrows);
 
+        [DllImport(__DllName, EntryPoint = "wv_entities_with", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_entities_with(Weave* wv, nuint len, byte** names);
+
+        [DllImport(__DllName, EntryPoint = "wv_referrers", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_referrers(Weave* wv, nuint entity);
+
+        [DllImport(__DllName, EntryPoint = "wv_query", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_query(Weave* wv, nuint len, nuint* it, byte* path);
+
+        [DllImport(__DllName, EntryPoint = "wv_entities_without", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_entities_without(Weave* wv, nuint len, byte** names);
+
         [DllImport(__DllName, EntryPoint = "wv_move__deps", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern WvEntityArray wv_move__deps(Weave* wv, nuint len, nuint* it);
 
@@ -172,12 +236,67 @@ namespace Weave
         [DllImport(__DllName, EntryPoint = "wv_move__up_n", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern WvEntityArray wv_move__up_n(Weave* wv, nuint len, nuint* it);
 
+        [DllImport(__DllName, EntryPoint = "wv_move__deps_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__deps_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__arrows_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__arrows_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__arrows_in_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__arrows_in_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__arrows_out_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__arrows_out_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__marks_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__marks_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__tethers_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__tethers_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__to_src_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__to_src_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__to_tgt_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__to_tgt_into(Weave* wv, nuint len, nuint* it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__prev_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__prev_into(Weave* wv, nuint it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__next_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__next_into(Weave* wv, nuint it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__down_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__down_into(Weave* wv, nuint it, nuint cap, nuint* @out);
+
+        [DllImport(__DllName, EntryPoint = "wv_move__up_into", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern nuint wv_move__up_into(Weave* wv, nuint it, nuint cap, nuint* @out);
+
         [DllImport(__DllName, EntryPoint = "wv_search__find_one", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern WvEntityArray wv_search__find_one(Weave* wv, nuint hoisted_pattern, nuint hoisted_target, nuint* size, nuint* count);
 
+        [DllImport(__DllName, EntryPoint = "wv_search__find_one_with", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_search__find_one_with(Weave* wv, nuint hoisted_pattern, nuint hoisted_target, WvMatchOptions options, nuint* size, nuint* count);
+
         [DllImport(__DllName, EntryPoint = "wv_search__find_all", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern WvEntityArray wv_search__find_all(Weave* wv, nuint hoisted_pattern, nuint hoisted_target, nuint* size, nuint* count);
 
+        [DllImport(__DllName, EntryPoint = "wv_search__find_all_with", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvEntityArray wv_search__find_all_with(Weave* wv, nuint hoisted_pattern, nuint hoisted_target, WvMatchOptions options, nuint* size, nuint* count);
+
+        [DllImport(__DllName, EntryPoint = "wv_search__begin", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvSearchCursor* wv_search__begin(Weave* wv, nuint hoisted_pattern, nuint hoisted_target, WvMatchOptions options, WvMatchLimits limits, delegate* unmanaged[Cdecl]<void*, bool> cancel, void* user_data);
+
+        [DllImport(__DllName, EntryPoint = "wv_search__next", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool wv_search__next(WvSearchCursor* cursor, WvEntityArray* solution);
+
+        [DllImport(__DllName, EntryPoint = "wv_search__stop_reason", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvStopReason wv_search__stop_reason(WvSearchCursor* cursor);
+
+        [DllImport(__DllName, EntryPoint = "wv_search__end", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void wv_search__end(WvSearchCursor* cursor);
+
         [DllImport(__DllName, EntryPoint = "wv_replace__replace", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern EntityId wv_replace__replace(Weave* wv, nuint hoisted_pattern, nuint hoisted_goal, nuint hoisted_target);
 
@@ -187,14 +306,27 @@ namespace Weave
         [DllImport(__DllName, EntryPoint = "wv_deserialize", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern EntityId wv_deserialize(Weave* wv, nuint len, byte* it);
 
+        [DllImport(__DllName, EntryPoint = "wv_upgrade", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern WvByteArray wv_upgrade(nuint len, byte* it);
 
+
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct WvDatatype
+    {
+        public WvDatatypeKind kind;
+        public WvDatatype* item;
+        public byte* name;
     }
 
     [StructLayout(LayoutKind.Sequential)]
     internal unsafe partial struct WvDataField
     {
         public byte* name;
-        public Datatype datatype;
+        public WvDatatype datatype;
+        public void* @default;
+        public RefPolicy on_delete;
     }
 
     [StructLayout(LayoutKind.Sequential)]
@@ -211,6 +343,51 @@ namespace Weave
         public byte* ptr;
     }
 
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct WvMatchOptions
+    {
+        [MarshalAs(UnmanagedType.U1)] public bool injective;
+        [MarshalAs(UnmanagedType.U1)] public bool induced;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct WvSearchCursor
+    {
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct WvMatchLimits
+    {
+        public nuint max_count;
+        public nuint max_steps;
+        public ulong timeout_ms;
+    }
+
+
+    internal enum WvDatatypeKind : uint
+    {
+        Entity,
+        Int,
+        Float,
+        Bool,
+        String,
+        Bytes,
+        List,
+        Optional,
+        Enum,
+        Vec2,
+        Vec3,
+        Vec4,
+    }
+
+    internal enum WvStopReason : uint
+    {
+        None,
+        Count,
+        Steps,
+        Timeout,
+        Cancelled,
+    }
 
 
 }
@@ -0,0 +1,185 @@
+use crate::core::{DataField, DataValue, Datatype, EntityId, Weave, WeaveError};
+
+pub use wv_derive::Component;
+
+/*
+    Rust types stored as components. Usually derived:
+
+        #[derive(Component)]
+        struct Health { hp: i64 }
+
+        wv.insert(e, Health { hp: 10 });
+        let health: Health = wv.get(e);
+ */
+pub trait Component: Sized {
+    const NAME: &'static str;
+
+    fn fields() -> Vec<DataField>;
+    fn to_values(&self) -> Vec<DataValue>;
+    fn from_values(values: &[DataValue]) -> Option<Self>;
+}
+
+/*
+    Rust types usable as component fields. `Entity` fields are entity
+    references, `Vec` fields are lists and `Option` fields are optionals.
+ */
+pub trait FieldValue: Sized {
+    fn datatype() -> Datatype;
+    fn to_value(&self) -> DataValue;
+    fn from_value(value: &DataValue) -> Option<Self>;
+}
+
+macro_rules! field_value {
+    ($ty:ty, $variant:ident) => {
+        impl FieldValue for $ty {
+            fn datatype() -> Datatype {
+                Datatype::$variant
+            }
+
+            fn to_value(&self) -> DataValue {
+                DataValue::$variant(self.clone())
+            }
+
+            fn from_value(value: &DataValue) -> Option<Self> {
+                match value {
+                    DataValue::$variant(v) => Some(v.clone()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+/*
+    An entity reference in a derived component. `EntityId` is a plain
+    `usize`, so a field only refers to an entity when it says so with this;
+    a `usize` field is stored as an integer and left alone by deletions.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Entity(pub EntityId);
+
+impl FieldValue for Entity {
+    fn datatype() -> Datatype {
+        Datatype::Entity
+    }
+
+    fn to_value(&self) -> DataValue {
+        DataValue::Entity(self.0)
+    }
+
+    fn from_value(value: &DataValue) -> Option<Self> {
+        match value {
+            DataValue::Entity(e) => Some(Entity(*e)),
+            _ => None,
+        }
+    }
+}
+
+impl FieldValue for usize {
+    fn datatype() -> Datatype {
+        Datatype::Int
+    }
+
+    fn to_value(&self) -> DataValue {
+        DataValue::Int(*self as i64)
+    }
+
+    fn from_value(value: &DataValue) -> Option<Self> {
+        match value {
+            DataValue::Int(v) => usize::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+}
+
+field_value!(i64, Int);
+field_value!(f64, Float);
+field_value!(bool, Bool);
+field_value!(String, String);
+field_value!([f64; 2], Vec2);
+field_value!([f64; 3], Vec3);
+field_value!([f64; 4], Vec4);
+
+impl<T: FieldValue> FieldValue for Vec<T> {
+    fn datatype() -> Datatype {
+        Datatype::List(Box::new(T::datatype()))
+    }
+
+    fn to_value(&self) -> DataValue {
+        DataValue::List(T::datatype(), self.iter().map(|v| v.to_value()).collect())
+    }
+
+    fn from_value(value: &DataValue) -> Option<Self> {
+        match value {
+            DataValue::List(_, items) => items.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FieldValue> FieldValue for Option<T> {
+    fn datatype() -> Datatype {
+        Datatype::Optional(Box::new(T::datatype()))
+    }
+
+    fn to_value(&self) -> DataValue {
+        DataValue::Optional(T::datatype(), self.as_ref().map(|v| Box::new(v.to_value())))
+    }
+
+    fn from_value(value: &DataValue) -> Option<Self> {
+        match value {
+            DataValue::Optional(_, Some(item)) => T::from_value(item).map(Some),
+            DataValue::Optional(_, None) => Some(None),
+            _ => None,
+        }
+    }
+}
+
+impl Weave {
+    /*
+        Defines the component's datatype; `insert` does this on first use
+     */
+    pub fn try_register<C: Component>(&mut self) -> Result<(), WeaveError> {
+        self.try_def_datatype(C::NAME, &C::fields()).map(|_| ())
+    }
+
+    pub fn register<C: Component>(&mut self) {
+        self.try_register::<C>().unwrap()
+    }
+
+    /*
+        Adds the component, or overwrites it if the entity already has one
+     */
+    pub fn try_insert<C: Component>(&mut self, entity: EntityId, component: C) -> Result<(), WeaveError> {
+        if self.try_get_datatype_id(C::NAME).is_err() {
+            self.try_register::<C>()?;
+        }
+        self.try_set_component(entity, C::NAME, &component.to_values())
+    }
+
+    pub fn insert<C: Component>(&mut self, entity: EntityId, component: C) {
+        self.try_insert(entity, component).unwrap()
+    }
+
+    pub fn try_get<C: Component>(&self, entity: EntityId) -> Result<C, WeaveError> {
+        let values = self.try_get_component(entity, C::NAME)?.to_values();
+        C::from_values(&values).ok_or_else(|| WeaveError::SchemaMismatch {
+            datatype: C::NAME.to_string(),
+            reason: "stored fields don't match the Rust type".to_string(),
+        })
+    }
+
+    pub fn get<C: Component>(&self, entity: EntityId) -> C {
+        self.try_get(entity).unwrap()
+    }
+
+    pub fn try_update<C: Component>(&mut self, entity: EntityId, update: impl FnOnce(&mut C)) -> Result<(), WeaveError> {
+        let mut component = self.try_get::<C>(entity)?;
+        update(&mut component);
+        self.try_set_component(entity, C::NAME, &component.to_values())
+    }
+
+    pub fn update<C: Component>(&mut self, entity: EntityId, update: impl FnOnce(&mut C)) {
+        self.try_update(entity, update).unwrap()
+    }
+}
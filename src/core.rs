@@ -1,22 +1,67 @@
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::hash::{ Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
 use multimap::MultiMap;
 use serde::{Deserialize, Serialize};
-
+use crate::storage::{ComponentRef, ComponentStore, DataRef, Interner};
+
+/*
+    Entity ids are packed handles: the low half of the bits holds the slot
+    index, the high half holds the generation of that slot. Deleting an
+    entity bumps its slot's generation, so ids held past deletion stop
+    being valid instead of aliasing whatever reuses the slot.
+ */
 pub type EntityId = usize;
 pub type DatatypeId = u64;
 
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const MAX_GENERATION: usize = usize::MAX >> INDEX_BITS;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityHandle {
+    pub index: usize,
+    pub generation: usize,
+}
+
+impl EntityHandle {
+    pub fn pack(&self) -> EntityId {
+        (self.generation << INDEX_BITS) | (self.index & INDEX_MASK)
+    }
+
+    pub fn unpack(id: EntityId) -> Self {
+        EntityHandle {
+            index: id & INDEX_MASK,
+            generation: id >> INDEX_BITS,
+        }
+    }
+}
+
+/*
+    Enums are declared by name with `Weave::def_enum` before a datatype can
+    use them; lists and optionals name the type of their items.
+ */
 #[repr(C)]
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
+#[derive(Serialize, Deserialize)]
 pub enum Datatype {
     Entity,
     Int,
     Float,
     Bool,
     String,
+    Bytes,
+    List(Box<Datatype>),
+    Optional(Box<Datatype>),
+    Enum(String),
+    Vec2,
+    Vec3,
+    Vec4,
 }
 
+/*
+    Lists and optionals carry their item type so that empty ones still
+    know what they hold; enum values are (enum name, variant name)
+ */
 #[repr(C)]
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
 #[derive(Serialize, Deserialize)]
@@ -26,63 +71,406 @@ pub enum DataValue {
     Float(f64),
     Bool(bool),
     String(String),
+    Bytes(Vec<u8>),
+    List(Datatype, Vec<DataValue>),
+    Optional(Datatype, Option<Box<DataValue>>),
+    Enum(String, String),
+    Vec2([f64; 2]),
+    Vec3([f64; 3]),
+    Vec4([f64; 4]),
+}
+
+impl DataValue {
+    pub fn datatype(&self) -> Datatype {
+        match self {
+            DataValue::Entity(_) => Datatype::Entity,
+            DataValue::Int(_) => Datatype::Int,
+            DataValue::Float(_) => Datatype::Float,
+            DataValue::Bool(_) => Datatype::Bool,
+            DataValue::String(_) => Datatype::String,
+            DataValue::Bytes(_) => Datatype::Bytes,
+            DataValue::List(item, _) => Datatype::List(Box::new(item.clone())),
+            DataValue::Optional(item, _) => Datatype::Optional(Box::new(item.clone())),
+            DataValue::Enum(name, _) => Datatype::Enum(name.clone()),
+            DataValue::Vec2(_) => Datatype::Vec2,
+            DataValue::Vec3(_) => Datatype::Vec3,
+            DataValue::Vec4(_) => Datatype::Vec4,
+        }
+    }
+
+    /*
+        The values nested in a list or optional
+     */
+    pub fn items(&self) -> Vec<&DataValue> {
+        match self {
+            DataValue::List(_, items) => items.iter().collect(),
+            DataValue::Optional(_, item) => item.iter().map(|v| v.as_ref()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/*
+    What happens to a component when an entity one of its fields refers
+    to is deleted: the reference becomes NIL, the entity holding the
+    component is deleted as well, or the deletion is refused
+ */
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialOrd, PartialEq)]
+pub enum RefPolicy {
+    #[default]
+    Nullify,
+    Cascade,
+    Restrict,
 }
 
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
 pub struct DataField {
     pub name: String,
     pub datatype: Datatype,
+    pub default: Option<DataValue>,
+    pub on_delete: RefPolicy,
+}
+
+impl DataField {
+    pub fn new(name: &str, datatype: Datatype) -> Self {
+        DataField { name: name.to_string(), datatype, default: None, on_delete: RefPolicy::default() }
+    }
+
+    /*
+        Only matters for fields holding entities
+     */
+    pub fn on_delete(mut self, policy: RefPolicy) -> Self {
+        self.on_delete = policy;
+        self
+    }
+
+    /*
+        Value the field takes when a component is added by name without it
+     */
+    pub fn with_default(mut self, value: DataValue) -> Self {
+        self.default = Some(value);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MotifKind {
+    Knot,
+    Arrow,
+    Mark,
+    Tether,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WeaveError {
+    InvalidEntity(EntityId),
+    StaleEntity(EntityId),
+    UnknownDatatype(String),
+    FieldIndexOutOfRange { datatype: String, index: usize },
+    ComponentMissing { entity: EntityId, datatype: String },
+    SchemaMismatch { datatype: String, reason: String },
+    ArityMismatch { datatype: String, expected: usize, found: usize },
+    FieldTypeMismatch { datatype: String, field: String, expected: Datatype, found: Datatype },
+    UnknownField { datatype: String, field: String },
+    ComponentExists { entity: EntityId, datatype: String },
+    DatatypeCollision { name: String, existing: String },
+    DatatypeRedefined(String),
+    FieldExists { datatype: String, field: String },
+    UnknownVariant { datatype: String, variant: String },
+    MissingField { datatype: String, field: String },
+    Referenced { entity: EntityId, referrer: EntityId, datatype: String },
+    BuiltinDatatype(String),
+    UnsupportedFormat(u64),
+    CorruptData(String),
+}
+
+impl std::fmt::Display for WeaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeaveError::InvalidEntity(id) => write!(f, "entity {} is not valid", id),
+            WeaveError::StaleEntity(id) => {
+                let handle = EntityHandle::unpack(*id);
+                write!(f, "entity {} is stale (generation {} of slot {} was deleted)", id, handle.generation, handle.index)
+            }
+            WeaveError::UnknownDatatype(name) => write!(f, "datatype '{}' is not defined", name),
+            WeaveError::FieldIndexOutOfRange { datatype, index } =>
+                write!(f, "datatype '{}' has no field at index {}", datatype, index),
+            WeaveError::ComponentMissing { entity, datatype } =>
+                write!(f, "entity {} has no '{}' component", entity, datatype),
+            WeaveError::SchemaMismatch { datatype, reason } =>
+                write!(f, "component doesn't match datatype '{}': {}", datatype, reason),
+            WeaveError::ArityMismatch { datatype, expected, found } =>
+                write!(f, "datatype '{}' has {} fields, got {} values", datatype, expected, found),
+            WeaveError::FieldTypeMismatch { datatype, field, expected, found } =>
+                write!(f, "field '{}' of datatype '{}' is {:?}, got {:?}", field, datatype, expected, found),
+            WeaveError::UnknownField { datatype, field } =>
+                write!(f, "datatype '{}' has no field '{}'", datatype, field),
+            WeaveError::ComponentExists { entity, datatype } =>
+                write!(f, "entity {} already has a '{}' component", entity, datatype),
+            WeaveError::DatatypeCollision { name, existing } =>
+                write!(f, "datatype '{}' hashes to the same id as '{}'", name, existing),
+            WeaveError::DatatypeRedefined(name) =>
+                write!(f, "datatype '{}' is already defined with different fields", name),
+            WeaveError::FieldExists { datatype, field } =>
+                write!(f, "datatype '{}' already has a field '{}'", datatype, field),
+            WeaveError::UnknownVariant { datatype, variant } =>
+                write!(f, "enum '{}' has no variant '{}'", datatype, variant),
+            WeaveError::MissingField { datatype, field } =>
+                write!(f, "field '{}' of datatype '{}' has no value and no default", field, datatype),
+            WeaveError::Referenced { entity, referrer, datatype } =>
+                write!(f, "entity {} can't be deleted, the '{}' component of {} refers to it", entity, datatype, referrer),
+            WeaveError::BuiltinDatatype(name) =>
+                write!(f, "datatype '{}' is built in and can't be changed or undefined", name),
+            WeaveError::UnsupportedFormat(version) =>
+                write!(f, "weave format version {} isn't supported", version),
+            WeaveError::CorruptData(reason) => write!(f, "serialized weave is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for WeaveError {}
+
+/*
+    One step of changing a datatype's fields; see `Weave::try_redefine_datatype`.
+    An added field fills existing instances with its default, so it needs
+    one unless there are none.
+ */
+pub enum Migration {
+    AddField(DataField),
+    RenameField { from: String, to: String },
+    DropField(String),
+    MapField { field: String, datatype: Datatype, map: Box<dyn Fn(&DataValue) -> DataValue> },
+}
+
+impl Migration {
+    fn field_index(datatype: &str, schema: &[DataField], field: &str) -> Result<usize, WeaveError> {
+        schema.iter().position(|f| f.name == field)
+            .ok_or_else(|| WeaveError::UnknownField { datatype: datatype.to_string(), field: field.to_string() })
+    }
+
+    fn check_absent(datatype: &str, schema: &[DataField], field: &str) -> Result<(), WeaveError> {
+        if schema.iter().any(|f| f.name == field) {
+            Err(WeaveError::FieldExists { datatype: datatype.to_string(), field: field.to_string() })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_type(datatype: &str, field: &DataField, value: &DataValue) -> Result<(), WeaveError> {
+        if field.datatype != value.datatype() {
+            Err(WeaveError::FieldTypeMismatch {
+                datatype: datatype.to_string(),
+                field: field.name.clone(),
+                expected: field.datatype.clone(),
+                found: value.datatype(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn apply(&self, datatype: &str, schema: &mut Vec<DataField>, instances: &mut [(EntityId, Vec<DataValue>)]) -> Result<(), WeaveError> {
+        match self {
+            Migration::AddField(field) => {
+                Self::check_absent(datatype, schema, &field.name)?;
+                if let Some(default) = &field.default {
+                    Self::check_type(datatype, field, default)?;
+                    instances.iter_mut().for_each(|(_, values)| values.push(default.clone()));
+                } else if !instances.is_empty() {
+                    return Err(WeaveError::MissingField { datatype: datatype.to_string(), field: field.name.clone() });
+                }
+                schema.push(field.clone());
+            }
+            Migration::RenameField { from, to } => {
+                let index = Self::field_index(datatype, schema, from)?;
+                if from != to {
+                    Self::check_absent(datatype, schema, to)?;
+                }
+                schema[index].name = to.clone();
+            }
+            Migration::DropField(name) => {
+                let index = Self::field_index(datatype, schema, name)?;
+                schema.remove(index);
+                instances.iter_mut().for_each(|(_, values)| { values.remove(index); });
+            }
+            Migration::MapField { field, datatype: new_type, map } => {
+                let index = Self::field_index(datatype, schema, field)?;
+                schema[index].datatype = new_type.clone();
+                for (_, values) in instances.iter_mut() {
+                    values[index] = map(&values[index]);
+                    Self::check_type(datatype, &schema[index], &values[index])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrowthPolicy {
+    Double,
+    Linear(usize),
+}
+
+pub struct WeaveBuilder {
+    capacity: usize,
+    growth: GrowthPolicy,
+    builtin_datatypes: bool,
+    strict_datatypes: bool,
+}
+
+impl Default for WeaveBuilder {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            growth: GrowthPolicy::Double,
+            builtin_datatypes: true,
+            strict_datatypes: false,
+        }
+    }
+}
+
+impl WeaveBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+        Number of entity slots allocated up front
+     */
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /*
+        How many slots get added once the preallocated ones run out
+     */
+    pub fn growth(mut self, growth: GrowthPolicy) -> Self {
+        self.growth = growth;
+        self
+    }
+
+    /*
+        Whether `Identity`, `With`, `Without`, `Where` and `Forbid` get
+        defined on build; search and replace rely on them being there
+     */
+    pub fn builtin_datatypes(mut self, enabled: bool) -> Self {
+        self.builtin_datatypes = enabled;
+        self
+    }
+
+    /*
+        In strict mode components can only be added for datatypes that were
+        defined beforehand, instead of inferring a schema on first use
+     */
+    pub fn strict_datatypes(mut self, strict: bool) -> Self {
+        self.strict_datatypes = strict;
+        self
+    }
+
+    pub fn build(self) -> Weave {
+        let mut wv = Weave {
+            available: self.capacity,
+            growth: self.growth,
+            strict_datatypes: self.strict_datatypes,
+            freelist: Vec::new(),
+            generations: vec![0; self.capacity],
+            identities: vec![Weave::NIL; self.capacity],
+            sources: vec![Weave::NIL; self.capacity],
+            targets: vec![Weave::NIL; self.capacity],
+            source_ids: vec![Vec::new(); self.capacity],
+            target_ids: vec![Vec::new(); self.capacity],
+            types: Default::default(),
+            type_names: Default::default(),
+            archetypes: Default::default(),
+            data: Default::default(),
+            enums: HashMap::new(),
+            referrers: HashMap::new(),
+            strings: Default::default(),
+        };
+
+        if self.builtin_datatypes {
+            wv.def_datatype("Identity", &[ DataField::new("id", Datatype::Entity) ]);
+            wv.def_datatype("With", &[ DataField::new("name", Datatype::String) ]);
+            wv.def_datatype("Without", &[ DataField::new("name", Datatype::String) ]);
+            wv.def_datatype("Where", &[
+                DataField::new("component", Datatype::String),
+                DataField::new("field", Datatype::String),
+                DataField::new("op", Datatype::String),
+                DataField::new("value", Datatype::String),
+            ]);
+            wv.def_datatype("Forbid", &[ DataField::new("nac", Datatype::Entity) ]);
+        }
+
+        wv
+    }
 }
 
 pub struct Weave {
     pub(crate) available: usize,
+    pub(crate) growth: GrowthPolicy,
+    pub(crate) strict_datatypes: bool,
     pub(crate) freelist: Vec<usize>,
+    pub(crate) generations: Vec<usize>,
     pub(crate) identities: Vec<usize>,
     pub(crate) sources: Vec<usize>,
     pub(crate) targets: Vec<usize>,
-    pub(crate) source_ids: HashMap<usize, HashSet<usize>>,
-    pub(crate) target_ids: HashMap<usize, HashSet<usize>>,
+    pub(crate) source_ids: Vec<Vec<EntityId>>,
+    pub(crate) target_ids: Vec<Vec<EntityId>>,
     pub(crate) type_names: HashMap<DatatypeId, String>,
     pub(crate) types: HashMap<DatatypeId, Vec<DataField>>,
+    pub(crate) enums: HashMap<String, Vec<String>>,
     pub(crate) archetypes: MultiMap<EntityId, DatatypeId>,
-    pub(crate) data: HashMap<DatatypeId, HashMap<usize, Vec<u8>>>,
+    pub(crate) data: HashMap<DatatypeId, ComponentStore>,
+    pub(crate) referrers: HashMap<EntityId, HashSet<(EntityId, DatatypeId)>>,
+    pub(crate) strings: Interner,
 }
 
-impl Weave {
-
-    pub(crate) fn new() -> Self {
-        let mut wv = Self {
-            available: 1024,
-            freelist: Vec::new(),
-            identities: vec![Self::NIL; 1024],
-            sources: vec![Self::NIL; 1024],
-            targets: vec![Self::NIL; 1024],
-            source_ids: Default::default(),
-            target_ids: Default::default(),
-            types: Default::default(),
-            type_names: Default::default(),
-            archetypes: Default::default(),
-            data: Default::default(),
-        };
+impl Default for Weave {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        wv.def_datatype("Identity", &[ DataField{ name: "id".to_string(), datatype: Datatype::Entity }]);
-        wv.def_datatype("With", &[ DataField{ name: "name".to_string(), datatype: Datatype::String }]);
-        wv.def_datatype("Without", &[ DataField{ name: "name".to_string(), datatype: Datatype::String }]);
+impl Weave {
+    /*
+        The datatypes search and replace rely on (see
+        `WeaveBuilder::builtin_datatypes`)
+     */
+    pub const BUILTIN_DATATYPES: &'static [&'static str] = &[ "Identity", "With", "Without", "Where", "Forbid" ];
+
+    pub fn new() -> Self {
+        WeaveBuilder::default().build()
+    }
 
-        wv
+    pub fn builder() -> WeaveBuilder {
+        WeaveBuilder::default()
     }
 
     pub(crate) const NIL: EntityId = usize::MAX;
 
     pub(crate) fn get_next_id(&mut self) -> EntityId {
-        if let Some(value) = self.freelist.pop() {
-            value
+        if let Some(index) = self.freelist.pop() {
+            EntityHandle { index, generation: self.generations[index] }.pack()
         } else {
             if self.available == 0 {
-                let added = self.identities.len();
-                self.identities.resize(2 * added, Self::NIL);
-                self.sources.resize(2 * added, Self::NIL);
-                self.targets.resize(2 * added, Self::NIL);
+                // slot indices have to fit below a handle's generation bits
+                let len = self.identities.len();
+                let room = INDEX_MASK + 1 - len;
+                assert!(room > 0, "weave is out of entity slots (at most {} entities)", INDEX_MASK + 1);
+                let added = match self.growth {
+                    GrowthPolicy::Double => len.max(1),
+                    GrowthPolicy::Linear(step) => step.max(1),
+                }.min(room);
+                self.generations.resize(len + added, 0);
+                self.identities.resize(len + added, Self::NIL);
+                self.sources.resize(len + added, Self::NIL);
+                self.targets.resize(len + added, Self::NIL);
+                self.source_ids.resize_with(len + added, Vec::new);
+                self.target_ids.resize_with(len + added, Vec::new);
                 self.available = added;
             }
 
@@ -90,63 +478,78 @@ impl Weave {
         }
     }
 
-    pub(crate) fn add_source(&mut self, src: EntityId, id: EntityId) {
-        self.sources[id] = src;
+    /*
+        Dependents live in one sorted vector per slot, so they can be
+        handed out as slices without copying
+     */
+    fn insert_dependent(dependents: &mut Vec<EntityId>, id: EntityId) {
+        if let Err(at) = dependents.binary_search(&id) {
+            dependents.insert(at, id);
+        }
+    }
 
-        if !self.source_ids.contains_key(&src) {
-            self.source_ids.insert(src, Default::default());
+    fn remove_dependent(dependents: &mut Vec<EntityId>, id: EntityId) {
+        if let Ok(at) = dependents.binary_search(&id) {
+            dependents.remove(at);
         }
+    }
 
-        self.source_ids.get_mut(&src).unwrap().insert(id);
+    pub(crate) fn add_source(&mut self, src: EntityId, id: EntityId) {
+        self.sources[Self::slot(id)] = src;
+        Self::insert_dependent(&mut self.source_ids[Self::slot(src)], id);
     }
 
     pub(crate) fn add_target(&mut self, tgt: EntityId, id: EntityId) {
-        self.targets[id] = tgt;
-
-        if !self.target_ids.contains_key(&tgt) {
-            self.target_ids.insert(tgt, Default::default());
-        }
-
-        self.target_ids.get_mut(&tgt).unwrap().insert(id);
+        self.targets[Self::slot(id)] = tgt;
+        Self::insert_dependent(&mut self.target_ids[Self::slot(tgt)], id);
     }
 
     pub(crate) fn remove_source(&mut self, src: EntityId, id: EntityId) {
-        self.sources[id] = Self::NIL;
-        if self.source_ids.contains_key(&src) {
-            self.source_ids.get_mut(&src).unwrap().remove(&id);
+        self.sources[Self::slot(id)] = Self::NIL;
+        if let Some(dependents) = self.source_ids.get_mut(Self::slot(src)) {
+            Self::remove_dependent(dependents, id);
         }
     }
 
     pub(crate) fn remove_target(&mut self, tgt: EntityId, id: EntityId) {
-        self.targets[id] = Self::NIL;
-        if self.target_ids.contains_key(&tgt) {
-            self.target_ids.get_mut(&tgt).unwrap().remove(&id);
+        self.targets[Self::slot(id)] = Self::NIL;
+        if let Some(dependents) = self.target_ids.get_mut(Self::slot(tgt)) {
+            Self::remove_dependent(dependents, id);
         }
     }
 
     pub(crate) fn get_external_dependents(&self, id: EntityId) -> Vec<EntityId> {
-        let entities = self.get_dependents(id);
-        entities.iter().filter(|&i| *i != id).cloned().collect()
+        self.dependents(id).filter(|&i| i != id).collect()
     }
 
     pub(crate) fn get_dependents(&self, id: EntityId) -> Vec<EntityId> {
-        let mut entities = self.get_dependents_for_source(id);
-        entities.extend_from_slice(&self.get_dependents_for_target(id));
-        entities.sort();
-        entities.dedup();
-        entities
+        self.dependents(id).collect()
+    }
+
+    /*
+        Merges the sorted dependents on either end, so every dependent
+        comes out once and in id order
+     */
+    pub(crate) fn dependents(&self, id: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+        let (mut sources, mut targets) = (self.get_dependents_for_source(id).iter().peekable(),
+                                          self.get_dependents_for_target(id).iter().peekable());
+        std::iter::from_fn(move || {
+            match (sources.peek(), targets.peek()) {
+                (Some(s), Some(t)) if s < t => sources.next(),
+                (Some(s), Some(t)) if s > t => targets.next(),
+                (Some(_), Some(_)) => { targets.next(); sources.next() }
+                (Some(_), None) => sources.next(),
+                (None, _) => targets.next(),
+            }.copied()
+        })
     }
 
-    pub(crate) fn get_dependents_for_source(&self, src: EntityId) -> Vec<EntityId> {
-        let mut v = self.source_ids.get(&src).unwrap_or(&HashSet::new()).clone().into_iter().collect::<Vec<_>>();
-        v.sort();
-        v
+    pub(crate) fn get_dependents_for_source(&self, src: EntityId) -> &[EntityId] {
+        if self.is_valid(src) { &self.source_ids[Self::slot(src)] } else { &[] }
     }
 
-    pub(crate) fn get_dependents_for_target(&self, tgt: EntityId) -> Vec<EntityId> {
-        let mut v = self.target_ids.get(&tgt).unwrap_or(&HashSet::new()).clone().into_iter().collect::<Vec<_>>();
-        v.sort();
-        v
+    pub(crate) fn get_dependents_for_target(&self, tgt: EntityId) -> &[EntityId] {
+        if self.is_valid(tgt) { &self.target_ids[Self::slot(tgt)] } else { &[] }
     }
 
     #[allow(dead_code)]
@@ -154,10 +557,49 @@ impl Weave {
         !self.freelist.is_empty()
     }
 
+    pub(crate) fn check_valid(&self, id: EntityId) -> Result<(), WeaveError> {
+        if self.is_valid(id) {
+            Ok(())
+        } else if self.is_stale(id) {
+            Err(WeaveError::StaleEntity(id))
+        } else {
+            Err(WeaveError::InvalidEntity(id))
+        }
+    }
+
+    fn slot(id: EntityId) -> usize {
+        id & INDEX_MASK
+    }
+
+    /*
+        Retires the current generation of the entity's slot; a slot whose
+        generations are used up is never handed out again
+     */
+    fn free_slot(&mut self, id: EntityId) {
+        let index = Self::slot(id);
+        self.remove_source(self.sources[index], id);
+        self.remove_target(self.targets[index], id);
+        self.identities[index] = Self::NIL;
+
+        if self.generations[index] < MAX_GENERATION {
+            self.generations[index] += 1;
+            self.freelist.push(index);
+        }
+
+        for datatype in self.get_archetype(id) {
+            self.unindex_references(id, datatype);
+        }
+        for attachments in self.data.values_mut() {
+            attachments.remove(id, &mut self.strings);
+        }
+        self.archetypes.remove(&id);
+        self.referrers.remove(&id);
+    }
+
     pub fn new_knot(&mut self) -> EntityId {
         let id = self.get_next_id();
-        assert_eq!(self.identities[id], Self::NIL);
-        self.identities[id] = id;
+        assert_eq!(self.identities[Self::slot(id)], Self::NIL);
+        self.identities[Self::slot(id)] = id;
         self.add_source(id, id);
         self.add_target(id, id);
 
@@ -165,128 +607,327 @@ impl Weave {
         id
     }
 
-    pub fn new_arrow(&mut self, src: EntityId, tgt: EntityId) -> EntityId {
-        assert_eq!(self.identities[src], src);
-        assert_eq!(self.identities[tgt], tgt);
+    pub fn try_new_arrow(&mut self, src: EntityId, tgt: EntityId) -> Result<EntityId, WeaveError> {
+        self.check_valid(src)?;
+        self.check_valid(tgt)?;
 
         let id = self.get_next_id();
-        assert_eq!(self.identities[id], Self::NIL);
+        assert_eq!(self.identities[Self::slot(id)], Self::NIL);
 
-        self.identities[id] = id;
+        self.identities[Self::slot(id)] = id;
         self.add_source(src, id);
         self.add_target(tgt, id);
 
         self.available -= 1;
-        id
+        Ok(id)
     }
 
-    pub fn new_tether(&mut self, src: EntityId) -> EntityId {
-        assert_eq!(self.identities[src], src);
+    pub fn new_arrow(&mut self, src: EntityId, tgt: EntityId) -> EntityId {
+        self.try_new_arrow(src, tgt).unwrap()
+    }
+
+    pub fn try_new_tether(&mut self, src: EntityId) -> Result<EntityId, WeaveError> {
+        self.check_valid(src)?;
 
         let id = self.get_next_id();
-        assert_eq!(self.identities[id], Self::NIL);
+        assert_eq!(self.identities[Self::slot(id)], Self::NIL);
 
-        self.identities[id] = id;
+        self.identities[Self::slot(id)] = id;
         self.add_source(src, id);
         self.add_target(id, id);
         self.available -= 1;
-        id
+        Ok(id)
     }
 
-    pub fn new_mark(&mut self, tgt: EntityId) -> EntityId {
-        assert_eq!(self.identities[tgt], tgt);
+    pub fn new_tether(&mut self, src: EntityId) -> EntityId {
+        self.try_new_tether(src).unwrap()
+    }
+
+    pub fn try_new_mark(&mut self, tgt: EntityId) -> Result<EntityId, WeaveError> {
+        self.check_valid(tgt)?;
 
         let id = self.get_next_id();
-        assert_eq!(self.identities[id], Self::NIL);
+        assert_eq!(self.identities[Self::slot(id)], Self::NIL);
 
-        self.identities[id] = id;
+        self.identities[Self::slot(id)] = id;
         self.add_source(id, id);
         self.add_target(tgt, id);
 
         self.available -= 1;
-        id
+        Ok(id)
+    }
+
+    pub fn new_mark(&mut self, tgt: EntityId) -> EntityId {
+        self.try_new_mark(tgt).unwrap()
+    }
+
+    pub fn try_src(&self, id: EntityId) -> Result<EntityId, WeaveError> {
+        self.check_valid(id)?;
+        Ok(self.sources[Self::slot(id)])
     }
 
     pub fn src(&self, id: EntityId) -> EntityId {
-        assert_eq!(self.identities[id], id);
-        self.sources[id]
+        self.try_src(id).unwrap()
+    }
+
+    pub fn try_tgt(&self, id: EntityId) -> Result<EntityId, WeaveError> {
+        self.check_valid(id)?;
+        Ok(self.targets[Self::slot(id)])
     }
 
     pub fn tgt(&self, id: EntityId) -> EntityId {
-        assert_eq!(self.identities[id], id);
-        self.targets[id]
+        self.try_tgt(id).unwrap()
     }
 
-    pub fn change_src(&mut self, id: EntityId, src: EntityId) {
-        assert_eq!(self.identities[id], id);
+    pub fn try_change_src(&mut self, id: EntityId, src: EntityId) -> Result<(), WeaveError> {
+        self.check_valid(id)?;
+        self.check_valid(src)?;
 
-        let old_source = self.sources[id];
+        let old_source = self.sources[Self::slot(id)];
         self.remove_source(old_source, id);
         self.add_source(src, id);
+        Ok(())
     }
 
-    pub fn change_tgt(&mut self, id: EntityId, tgt: EntityId) {
-        assert_eq!(self.identities[id], id);
+    pub fn change_src(&mut self, id: EntityId, src: EntityId) {
+        self.try_change_src(id, src).unwrap()
+    }
 
-        let old_target = self.targets[id];
+    pub fn try_change_tgt(&mut self, id: EntityId, tgt: EntityId) -> Result<(), WeaveError> {
+        self.check_valid(id)?;
+        self.check_valid(tgt)?;
+
+        let old_target = self.targets[Self::slot(id)];
         self.remove_target(old_target, id);
         self.add_target(tgt, id);
+        Ok(())
+    }
+
+    pub fn change_tgt(&mut self, id: EntityId, tgt: EntityId) {
+        self.try_change_tgt(id, tgt).unwrap()
+    }
+
+    pub fn try_change_ends(&mut self, id: EntityId, src: EntityId, tgt: EntityId) -> Result<(), WeaveError> {
+        self.check_valid(id)?;
+        self.check_valid(src)?;
+        self.check_valid(tgt)?;
+
+        self.try_change_src(id, src)?;
+        self.try_change_tgt(id, tgt)
     }
 
     pub fn change_ends(&mut self, id: EntityId, src: EntityId, tgt: EntityId) {
-        self.change_src(id, src);
-        self.change_tgt(id, tgt);
+        self.try_change_ends(id, src, tgt).unwrap()
+    }
+
+    pub fn try_is_knot(&self, id: EntityId) -> Result<bool, WeaveError> {
+        Ok(self.try_src(id)? == id && self.try_tgt(id)? == id)
     }
 
     pub fn is_knot(&self, id: EntityId) -> bool {
-        self.src(id) == id && self.tgt(id) == id
+        self.try_is_knot(id).unwrap()
+    }
+
+    pub fn try_is_arrow(&self, id: EntityId) -> Result<bool, WeaveError> {
+        Ok(self.try_src(id)? != id && self.try_tgt(id)? != id)
     }
 
     pub fn is_arrow(&self, id: EntityId) -> bool {
-        self.src(id) != id && self.tgt(id) != id
+        self.try_is_arrow(id).unwrap()
+    }
+
+    pub fn try_is_mark(&self, id: EntityId) -> Result<bool, WeaveError> {
+        Ok(self.try_src(id)? == id && self.try_tgt(id)? != id)
     }
 
     pub fn is_mark(&self, id: EntityId) -> bool {
-        self.src(id) == id && self.tgt(id) != id
+        self.try_is_mark(id).unwrap()
+    }
+
+    pub fn try_is_tether(&self, id: EntityId) -> Result<bool, WeaveError> {
+        Ok(self.try_src(id)? != id && self.try_tgt(id)? == id)
     }
 
     pub fn is_tether(&self, id: EntityId) -> bool {
-        self.src(id) != id && self.tgt(id) == id
+        self.try_is_tether(id).unwrap()
+    }
+
+    pub fn try_motif_kind(&self, id: EntityId) -> Result<MotifKind, WeaveError> {
+        let (src, tgt) = (self.try_src(id)?, self.try_tgt(id)?);
+        Ok(match (src == id, tgt == id) {
+            (true, true) => MotifKind::Knot,
+            (false, false) => MotifKind::Arrow,
+            (true, false) => MotifKind::Mark,
+            (false, true) => MotifKind::Tether,
+        })
+    }
+
+    pub fn motif_kind(&self, id: EntityId) -> MotifKind {
+        self.try_motif_kind(id).unwrap()
+    }
+
+    /*
+        Everything a cascading delete of the entities would take with it
+     */
+    fn cascade_closure(&self, roots: &[EntityId], deleted: &mut HashSet<EntityId>) {
+        let mut unfinished = roots.to_vec();
+        while let Some(next) = unfinished.pop() {
+            if !self.is_valid(next) || !deleted.insert(next) {
+                continue;
+            }
+
+            unfinished.extend(self.get_dependents_for_source(next));
+            unfinished.extend(self.get_dependents_for_target(next));
+        }
+    }
+
+    /*
+        Applies the reference policies of components pointing into the
+        entities about to be deleted. Returns the holders to delete along
+        with them, or an error (without changing anything) if a reference
+        restricts the deletion.
+     */
+    fn release_references(&mut self, deleted: &mut HashSet<EntityId>) -> Result<Vec<EntityId>, WeaveError> {
+        let mut cascaded = vec![];
+        let mut nullified = vec![];
+        // walked in id order so the same deletion frees the same slots in
+        // the same order every run
+        let mut pending = deleted.iter().copied().collect::<Vec<_>>();
+        pending.sort_unstable_by(|a, b| b.cmp(a));
+        while let Some(entity) = pending.pop() {
+            let mut holders = self.referrers.get(&entity).into_iter().flatten().copied().collect::<Vec<_>>();
+            holders.sort_unstable();
+            for (holder, datatype) in holders {
+                if deleted.contains(&holder) {
+                    continue;
+                }
+
+                match self.reference_policy(holder, datatype, entity) {
+                    RefPolicy::Restrict => return Err(WeaveError::Referenced {
+                        entity,
+                        referrer: holder,
+                        datatype: self.type_names[&datatype].clone(),
+                    }),
+                    RefPolicy::Cascade => {
+                        let before = deleted.clone();
+                        self.cascade_closure(&[holder], deleted);
+                        let mut added = deleted.difference(&before).copied().collect::<Vec<_>>();
+                        added.sort_unstable_by(|a, b| b.cmp(a));
+                        pending.extend(added);
+                        cascaded.push(holder);
+                    }
+                    RefPolicy::Nullify => nullified.push((holder, datatype)),
+                }
+            }
+        }
+
+        for (holder, datatype) in nullified {
+            if !deleted.contains(&holder) {
+                self.nullify_references(holder, datatype, deleted);
+            }
+        }
+
+        Ok(cascaded)
+    }
+
+    /*
+        The strictest policy among the fields of the component that refer
+        to the entity
+     */
+    fn reference_policy(&self, holder: EntityId, datatype: DatatypeId, entity: EntityId) -> RefPolicy {
+        let schema = &self.types[&datatype];
+        self.get_component_by_id(holder, datatype)
+            .map(|c| c.iter().enumerate()
+                .filter(|(_, v)| v.entities().contains(&entity))
+                .map(|(i, _)| schema[i].on_delete)
+                .fold(RefPolicy::Nullify, |a, b| if b > a { b } else { a }))
+            .unwrap_or_default()
+    }
+
+    fn nullify_references(&mut self, holder: EntityId, datatype: DatatypeId, deleted: &HashSet<EntityId>) {
+        fn nullify(value: &mut DataValue, deleted: &HashSet<EntityId>) {
+            match value {
+                DataValue::Entity(e) if deleted.contains(e) => *e = Weave::NIL,
+                DataValue::List(_, items) => items.iter_mut().for_each(|v| nullify(v, deleted)),
+                DataValue::Optional(_, Some(item)) => nullify(item, deleted),
+                _ => {}
+            }
+        }
+
+        let Some(mut values) = self.get_component_by_id(holder, datatype).map(|c| c.to_values()) else {
+            return;
+        };
+        values.iter_mut().for_each(|v| nullify(v, deleted));
+
+        self.unindex_references(holder, datatype);
+        self.data.get_mut(&datatype).unwrap().set(holder, &values, &mut self.strings);
+        self.index_references(holder, datatype);
+    }
+
+    pub fn try_delete_orphan(&mut self, id: EntityId) -> Result<(), WeaveError> {
+        self.check_valid(id)?;
+        let mut deleted = HashSet::from([ id ]);
+        for holder in self.release_references(&mut deleted)? {
+            self.free_cascade(holder);
+        }
+
+        self.free_orphan(id);
+        Ok(())
     }
 
+    pub fn try_delete_cascade(&mut self, id: EntityId) -> Result<(), WeaveError> {
+        self.check_valid(id)?;
+        let mut deleted = HashSet::new();
+        self.cascade_closure(&[ id ], &mut deleted);
+        for holder in self.release_references(&mut deleted)? {
+            self.free_cascade(holder);
+        }
+
+        self.free_cascade(id);
+        Ok(())
+    }
+
+    /*
+        Deleting an invalid entity does nothing; deleting one that a
+        restricting reference protects panics
+     */
     pub fn delete_orphan(&mut self, id: EntityId) {
+        if self.is_valid(id) {
+            self.try_delete_orphan(id).unwrap()
+        }
+    }
+
+    pub fn delete_cascade(&mut self, id: EntityId) {
+        if self.is_valid(id) {
+            self.try_delete_cascade(id).unwrap()
+        }
+    }
+
+    fn free_orphan(&mut self, id: EntityId) {
         enum OrphanKind {
             Src(usize), Tgt(usize),
         }
 
         let mut unfinished = VecDeque::new();
 
-        if self.identities[id] != id {
+        if !self.is_valid(id) {
             return;
         }
 
-        self.identities[id] = Self::NIL;
-        self.freelist.push(id);
+        self.free_slot(id);
 
-        if let Some(sources) = self.source_ids.get(&id) {
-            for src in sources {
-                if *src != id {
-                    unfinished.push_back(OrphanKind::Src(*src));
-                }
+        for src in std::mem::take(&mut self.source_ids[Self::slot(id)]) {
+            if src != id {
+                unfinished.push_back(OrphanKind::Src(src));
             }
         }
 
-        if let Some(targets) = self.target_ids.get(&id) {
-            for tgt in targets {
-                if *tgt != id {
-                    unfinished.push_back(OrphanKind::Tgt(*tgt));
-                }
+        for tgt in std::mem::take(&mut self.target_ids[Self::slot(id)]) {
+            if tgt != id {
+                unfinished.push_back(OrphanKind::Tgt(tgt));
             }
         }
 
-        self.source_ids.remove(&id);
-        self.target_ids.remove(&id);
-
         while let Some(next) = unfinished.pop_front() {
             match next {
                 OrphanKind::Src(src) => self.change_src(src, src),
@@ -295,127 +936,529 @@ impl Weave {
         }
     }
 
-    pub fn delete_cascade(&mut self, id: EntityId) {
+    fn free_cascade(&mut self, id: EntityId) {
         let mut unfinished = VecDeque::new();
         unfinished.push_back(id);
 
         while let Some(next) = unfinished.pop_front() {
-            if self.identities[next] != next {
+            if !self.is_valid(next) {
                 continue;
             }
 
-            self.identities[next] = Self::NIL;
-            self.freelist.push(next);
+            self.free_slot(next);
+            unfinished.extend(std::mem::take(&mut self.source_ids[Self::slot(next)]));
+            unfinished.extend(std::mem::take(&mut self.target_ids[Self::slot(next)]));
+        }
+    }
 
-            if let Some(sources) = self.source_ids.get(&next) {
-                for src in sources {
-                    unfinished.push_back(*src);
-                }
-            }
+    pub fn is_valid(&self, id: EntityId) -> bool {
+        id != Self::NIL && self.identities.get(Self::slot(id)) == Some(&id)
+    }
 
-            if let Some(targets) = self.target_ids.get(&next) {
-                for tgt in targets {
-                    unfinished.push_back(*tgt);
-                }
-            }
+    pub fn is_stale(&self, id: EntityId) -> bool {
+        let handle = EntityHandle::unpack(id);
+        id != Self::NIL && self.generations.get(handle.index).is_some_and(|g| *g > handle.generation)
+    }
 
-            self.source_ids.remove(&next);
-            self.target_ids.remove(&next);
-        }
+    pub fn entity_index(id: EntityId) -> usize {
+        EntityHandle::unpack(id).index
     }
 
-    pub fn is_valid(&self, id: EntityId) -> bool {
-        self.identities[id] == id
+    pub fn entity_generation(id: EntityId) -> usize {
+        EntityHandle::unpack(id).generation
     }
 
     pub fn is_nil(&self, id: EntityId) -> bool {
         id == Self::NIL
     }
 
-    fn get_type_id(name: &str) -> DatatypeId {
-        let mut hasher = DefaultHasher::new();
-        name.hash(&mut hasher);
-        hasher.finish()
+    /*
+        FNV-1a over the name's bytes: datatype ids end up in serialized
+        weaves, so they must not depend on the platform or compiler version
+     */
+    pub(crate) fn get_type_id(name: &str) -> DatatypeId {
+        const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const PRIME: u64 = 0x100000001b3;
+
+        name.bytes().fold(OFFSET_BASIS, |hash, b| (hash ^ b as u64).wrapping_mul(PRIME))
     }
 
-    pub fn def_datatype(&mut self, name: &str, datatype: &[DataField]) -> DatatypeId {
+    pub(crate) fn resolve_type_id(&self, name: &str) -> Option<DatatypeId> {
         let id = Self::get_type_id(name);
+        match self.type_names.get(&id) {
+            Some(existing) if existing == name => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn try_def_datatype(&mut self, name: &str, datatype: &[DataField]) -> Result<DatatypeId, WeaveError> {
+        let id = Self::get_type_id(name);
+        if id == Self::NIL as u64 {
+            return Err(WeaveError::DatatypeCollision { name: name.to_string(), existing: "NIL".to_string() });
+        }
+
+        for field in datatype {
+            self.check_datatype(&field.datatype)?;
+            if let Some(default) = &field.default {
+                self.check_value(name, &field.name, &field.datatype, default)?;
+            }
+        }
+
+        if let Some(existing) = self.type_names.get(&id) {
+            if existing != name {
+                return Err(WeaveError::DatatypeCollision { name: name.to_string(), existing: existing.clone() });
+            }
+
+            if self.types[&id] != datatype {
+                return Err(WeaveError::DatatypeRedefined(name.to_string()));
+            }
+        }
+
         self.types.entry(id).or_insert(datatype.to_vec());
         self.type_names.entry(id).or_insert(name.to_string());
 
-        id
+        Ok(id)
     }
 
-    pub fn get_datatype_id(&self, name: &str) -> DatatypeId {
-        let id = Self::get_type_id(name);
-        if self.types.contains_key(&id) {
-            id
-        } else {
-            Self::NIL as u64
+    /*
+        Changes the fields of a defined datatype, applying the migrations in
+        order to the schema and to every stored instance. Nothing changes if
+        any of the migrations fails. The builtin datatypes can't be changed.
+     */
+    pub fn try_redefine_datatype(&mut self, name: &str, migrations: &[Migration]) -> Result<(), WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        if Self::BUILTIN_DATATYPES.contains(&name) {
+            return Err(WeaveError::BuiltinDatatype(name.to_string()));
+        }
+        let mut schema = self.types[&id].clone();
+        let mut instances = match self.data.get(&id) {
+            Some(store) => store.entities.iter()
+                .map(|e| (*e, self.get_component(*e, name).to_values()))
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+
+        for migration in migrations {
+            migration.apply(name, &mut schema, &mut instances)?;
         }
+        for (index, field) in schema.iter().enumerate() {
+            self.check_datatype(&field.datatype)?;
+            for value in field.default.iter().chain(instances.iter().map(|(_, values)| &values[index])) {
+                self.check_value(name, &field.name, &field.datatype, value)?;
+            }
+        }
+
+        let mut store = ComponentStore::new(&schema, &self.enums);
+        for (entity, values) in &instances {
+            store.insert(*entity, values, &mut self.strings);
+        }
+
+        for (entity, _) in &instances {
+            self.unindex_references(*entity, id);
+        }
+        self.types.insert(id, schema);
+        if let Some(old) = self.data.get_mut(&id) {
+            std::mem::replace(old, store).release(&mut self.strings);
+        }
+        for (entity, _) in &instances {
+            self.index_references(*entity, id);
+        }
+        Ok(())
     }
 
-    pub fn get_datatype_field_count(&self, name: &str) -> usize {
-        let id = Self::get_type_id(name);
-        if self.types.contains_key(&id) {
-            self.types[&id].len()
-        } else {
-            0
+    pub fn redefine_datatype(&mut self, name: &str, migrations: &[Migration]) {
+        self.try_redefine_datatype(name, migrations).unwrap()
+    }
+
+    /*
+        Forgets the datatype along with every component of that type. The
+        builtin datatypes can't be undefined.
+     */
+    pub fn try_undef_datatype(&mut self, name: &str) -> Result<(), WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        if Self::BUILTIN_DATATYPES.contains(&name) {
+            return Err(WeaveError::BuiltinDatatype(name.to_string()));
         }
+        let holders = self.data.get(&id).map(|store| store.entities.clone()).unwrap_or_default();
+        for holder in holders {
+            self.unindex_references(holder, id);
+        }
+        if let Some(store) = self.data.remove(&id) {
+            store.release(&mut self.strings);
+            for entity in store.entities {
+                if let Some(archetypes) = self.archetypes.get_vec_mut(&entity) {
+                    archetypes.retain(|t| *t != id);
+                    if archetypes.is_empty() {
+                        self.archetypes.remove(&entity);
+                    }
+                }
+            }
+        }
+
+        self.types.remove(&id);
+        self.type_names.remove(&id);
+        Ok(())
+    }
+
+    pub fn undef_datatype(&mut self, name: &str) {
+        self.try_undef_datatype(name).unwrap()
+    }
+
+    pub fn def_datatype(&mut self, name: &str, datatype: &[DataField]) -> DatatypeId {
+        self.try_def_datatype(name, datatype).unwrap()
+    }
+
+    pub fn try_get_datatype_id(&self, name: &str) -> Result<DatatypeId, WeaveError> {
+        self.resolve_type_id(name)
+            .ok_or_else(|| WeaveError::UnknownDatatype(name.to_string()))
+    }
+
+    pub fn get_datatype_id(&self, name: &str) -> DatatypeId {
+        self.try_get_datatype_id(name).unwrap_or(Self::NIL as u64)
+    }
+
+    pub fn try_get_datatype_field_count(&self, name: &str) -> Result<usize, WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        Ok(self.types[&id].len())
+    }
+
+    pub fn get_datatype_field_count(&self, name: &str) -> usize {
+        self.try_get_datatype_field_count(name).unwrap_or(0)
+    }
+
+    pub fn try_get_datatype_field(&self, name: &str, index: usize) -> Result<DataField, WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        self.types[&id].get(index).cloned()
+            .ok_or_else(|| WeaveError::FieldIndexOutOfRange { datatype: name.to_string(), index })
     }
 
     pub fn get_datatype_field(&self, name: &str, index: usize) -> DataField {
-        let id = Self::get_type_id(name);
-        assert!(self.types.contains_key(&id));
-        assert!(index < self.types[&id].len());
-        self.types[&id][index].clone()
+        self.try_get_datatype_field(name, index).unwrap()
     }
 
-    pub(crate) fn add_component_raw(&mut self, entity: EntityId, name: &str, dat: &[u8]) {
-        let id = Self::get_type_id(name);
+    /*
+        Datatypes used before being defined take their schema from the
+        first component added under that name
+     */
+    fn infer_datatype(&mut self, name: &str, fields: &[DataValue]) -> Result<DatatypeId, WeaveError> {
+        let inferred = fields.iter().enumerate()
+            .map(|(i, v)| DataField::new(&i.to_string(), v.datatype()))
+            .collect::<Vec<_>>();
+        self.try_def_datatype(name, &inferred)
+    }
+
+    pub fn set_strict_datatypes(&mut self, strict: bool) {
+        self.strict_datatypes = strict;
+    }
+
+    pub fn is_strict_datatypes(&self) -> bool {
+        self.strict_datatypes
+    }
+
+    pub fn validate_component(&self, name: &str, fields: &[DataValue]) -> Result<(), WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        let schema = &self.types[&id];
+        if schema.len() != fields.len() {
+            return Err(WeaveError::ArityMismatch {
+                datatype: name.to_string(),
+                expected: schema.len(),
+                found: fields.len(),
+            });
+        }
+
+        for (field, value) in schema.iter().zip(fields) {
+            self.check_value(name, &field.name, &field.datatype, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_value(&self, name: &str, field: &str, datatype: &Datatype, value: &DataValue) -> Result<(), WeaveError> {
+        if *datatype != value.datatype() {
+            return Err(WeaveError::FieldTypeMismatch {
+                datatype: name.to_string(),
+                field: field.to_string(),
+                expected: datatype.clone(),
+                found: value.datatype(),
+            });
+        }
 
-        if !self.data.contains_key(&id) {
-            self.data.insert(id, Default::default());
+        match (datatype, value) {
+            (Datatype::List(item), _) | (Datatype::Optional(item), _) =>
+                value.items().into_iter().try_for_each(|v| self.check_value(name, field, item, v)),
+            (Datatype::Enum(enum_name), DataValue::Enum(_, variant)) => {
+                match self.enums.get(enum_name) {
+                    Some(variants) if variants.contains(variant) => Ok(()),
+                    Some(_) => Err(WeaveError::UnknownVariant { datatype: enum_name.clone(), variant: variant.clone() }),
+                    None => Err(WeaveError::UnknownDatatype(enum_name.clone())),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_datatype(&self, datatype: &Datatype) -> Result<(), WeaveError> {
+        match datatype {
+            Datatype::List(item) | Datatype::Optional(item) => self.check_datatype(item),
+            Datatype::Enum(name) if !self.enums.contains_key(name) => Err(WeaveError::UnknownDatatype(name.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+        Declares an enum that datatypes can then use as `Datatype::Enum(name)`.
+        Declaring it again with the same variants does nothing.
+     */
+    pub fn try_def_enum(&mut self, name: &str, variants: &[&str]) -> Result<(), WeaveError> {
+        let variants = variants.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        match self.enums.get(name) {
+            Some(existing) if *existing != variants => Err(WeaveError::DatatypeRedefined(name.to_string())),
+            Some(_) => Ok(()),
+            None => {
+                self.enums.insert(name.to_string(), variants);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn def_enum(&mut self, name: &str, variants: &[&str]) {
+        self.try_def_enum(name, variants).unwrap()
+    }
+
+    pub fn get_enum_variants(&self, name: &str) -> Option<&[String]> {
+        self.enums.get(name).map(|v| v.as_slice())
+    }
+
+    fn prepare_component(&mut self, entity: EntityId, name: &str, fields: &[DataValue]) -> Result<DatatypeId, WeaveError> {
+        self.check_valid(entity)?;
+        let id = match self.try_get_datatype_id(name) {
+            Ok(id) => id,
+            Err(e) if self.strict_datatypes => return Err(e),
+            Err(_) => self.infer_datatype(name, fields)?,
+        };
+        self.validate_component(name, fields)?;
+
+        let store = self.data.entry(id).or_insert_with(|| ComponentStore::new(&self.types[&id], &self.enums));
+        if !store.contains(entity) {
             self.archetypes.insert(entity, id);
         }
 
-        self.data.get_mut(&id).unwrap().entry(entity)
-            .or_insert(dat.to_vec());
+        Ok(id)
+    }
+
+    pub fn try_add_component(&mut self, entity: EntityId, name: &str, fields: &[DataValue]) -> Result<(), WeaveError> {
+        if self.has_component(entity, name) {
+            return Err(WeaveError::ComponentExists { entity, datatype: name.to_string() });
+        }
+
+        let id = self.prepare_component(entity, name, fields)?;
+        self.data.get_mut(&id).unwrap().insert(entity, fields, &mut self.strings);
+        self.index_references(entity, id);
+        Ok(())
     }
 
+    /*
+        Like `try_add_component`, but leaves a component the entity already
+        has as it is instead of failing
+     */
     pub fn add_component(&mut self, entity: EntityId, name: &str, fields: &[DataValue]) {
-        self.add_component_raw(entity, name, serde_json::to_string(&fields)
-            .expect("Fields can't stringify").as_bytes());
+        match self.try_add_component(entity, name, fields) {
+            Ok(()) | Err(WeaveError::ComponentExists { .. }) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /*
+        Orders named field values by the datatype's schema, filling the
+        missing ones from their defaults. An undefined datatype takes its
+        schema from the given fields, unless datatypes are strict; it is
+        only defined once a component is actually added with it.
+     */
+    pub fn try_get_component_values(&self, name: &str, fields: &[(&str, DataValue)]) -> Result<Vec<DataValue>, WeaveError> {
+        let inferred;
+        let schema = match self.try_get_datatype_id(name) {
+            Ok(id) => &self.types[&id],
+            Err(e) if self.strict_datatypes => return Err(e),
+            Err(_) => {
+                inferred = Self::infer_schema(fields);
+                &inferred
+            }
+        };
+
+        for (i, (field, _)) in fields.iter().enumerate() {
+            if !schema.iter().any(|f| f.name == *field) {
+                return Err(WeaveError::UnknownField { datatype: name.to_string(), field: field.to_string() });
+            }
+
+            if fields[..i].iter().any(|(f, _)| f == field) {
+                return Err(WeaveError::SchemaMismatch {
+                    datatype: name.to_string(),
+                    reason: format!("field '{}' is given more than once", field),
+                });
+            }
+        }
+
+        schema.iter().map(|f| {
+            fields.iter().find(|(n, _)| *n == f.name).map(|(_, v)| v.clone())
+                .or_else(|| f.default.clone())
+                .ok_or_else(|| WeaveError::MissingField { datatype: name.to_string(), field: f.name.clone() })
+        }).collect()
+    }
+
+    fn infer_schema(fields: &[(&str, DataValue)]) -> Vec<DataField> {
+        fields.iter().map(|(f, v)| DataField::new(f, v.datatype())).collect()
+    }
+
+    pub fn try_add_component_named(&mut self, entity: EntityId, name: &str, fields: &[(&str, DataValue)]) -> Result<(), WeaveError> {
+        let values = self.try_get_component_values(name, fields)?;
+        if self.try_get_datatype_id(name).is_err() {
+            self.try_def_datatype(name, &Self::infer_schema(fields))?;
+        }
+        self.try_add_component(entity, name, &values)
+    }
+
+    pub fn add_component_named(&mut self, entity: EntityId, name: &str, fields: &[(&str, DataValue)]) {
+        match self.try_add_component_named(entity, name, fields) {
+            Ok(()) | Err(WeaveError::ComponentExists { .. }) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /*
+        Adds the component, or overwrites all of its fields if the entity
+        already has it
+     */
+    pub fn try_set_component(&mut self, entity: EntityId, name: &str, fields: &[DataValue]) -> Result<(), WeaveError> {
+        let id = self.prepare_component(entity, name, fields)?;
+        self.unindex_references(entity, id);
+        self.data.get_mut(&id).unwrap().set(entity, fields, &mut self.strings);
+        self.index_references(entity, id);
+        Ok(())
+    }
+
+    pub fn set_component(&mut self, entity: EntityId, name: &str, fields: &[DataValue]) {
+        self.try_set_component(entity, name, fields).unwrap()
+    }
+
+    pub fn try_get_datatype_field_index(&self, name: &str, field_name: &str) -> Result<usize, WeaveError> {
+        let id = self.try_get_datatype_id(name)?;
+        self.types[&id].iter().position(|f| f.name == field_name)
+            .ok_or_else(|| WeaveError::UnknownField { datatype: name.to_string(), field: field_name.to_string() })
+    }
+
+    pub fn try_update_component_field(&mut self, entity: EntityId, name: &str, field_name: &str, value: DataValue) -> Result<(), WeaveError> {
+        let index = self.try_get_datatype_field_index(name, field_name)?;
+        self.try_get_component(entity, name)?;
+
+        let id = self.try_get_datatype_id(name)?;
+        let field = &self.types[&id][index];
+        self.check_value(name, &field.name, &field.datatype, &value)?;
+
+        self.unindex_references(entity, id);
+        self.data.get_mut(&id).unwrap().set_field(entity, index, &value, &mut self.strings);
+        self.index_references(entity, id);
+        Ok(())
+    }
+
+    pub fn update_component_field(&mut self, entity: EntityId, name: &str, field_name: &str, value: DataValue) {
+        self.try_update_component_field(entity, name, field_name, value).unwrap()
+    }
+
+    pub fn try_get_component_field(&self, entity: EntityId, name: &str, field_name: &str) -> Result<DataRef<'_>, WeaveError> {
+        let index = self.try_get_datatype_field_index(name, field_name)?;
+        let component = self.try_get_component(entity, name)?;
+        Ok(component.get(index).unwrap())
+    }
+
+    pub fn get_component_field(&self, entity: EntityId, name: &str, field_name: &str) -> DataRef<'_> {
+        self.try_get_component_field(entity, name, field_name).unwrap()
     }
 
     pub fn has_component(&self, entity: EntityId, name: &str) -> bool {
-        let id = Self::get_type_id(name);
-        if let Some(attachments) = self.data.get(&id) {
-            attachments.contains_key(&entity)
+        if let Some(attachments) = self.resolve_type_id(name).and_then(|id| self.data.get(&id)) {
+            attachments.contains(entity)
         } else {
             false
         }
     }
 
-    pub fn get_component(&self, entity: EntityId, name: &str) -> Vec<DataValue> {
-        let id = Self::get_type_id(name);
-        if let Some(attachments) = self.data.get(&id) {
-            let v= attachments.get(&entity).unwrap();
-            let s = std::str::from_utf8(v).unwrap().to_owned();
-            serde_json::from_str(&s).unwrap()
-        } else {
-            vec![]
+    pub fn try_get_component(&self, entity: EntityId, name: &str) -> Result<ComponentRef<'_>, WeaveError> {
+        self.check_valid(entity)?;
+        self.resolve_type_id(name)
+            .and_then(|id| self.data.get(&id))
+            .and_then(|store| store.rows.get(&entity).map(|row| ComponentRef { store, row: *row, strings: &self.strings }))
+            .ok_or_else(|| WeaveError::ComponentMissing { entity, datatype: name.to_string() })
+    }
+
+    pub fn get_component(&self, entity: EntityId, name: &str) -> ComponentRef<'_> {
+        self.try_get_component(entity, name).unwrap()
+    }
+
+    fn get_component_by_id(&self, entity: EntityId, datatype: DatatypeId) -> Option<ComponentRef<'_>> {
+        self.data.get(&datatype)
+            .and_then(|store| store.rows.get(&entity).map(|row| ComponentRef { store, row: *row, strings: &self.strings }))
+    }
+
+    fn component_references(&self, holder: EntityId, datatype: DatatypeId) -> Vec<EntityId> {
+        self.get_component_by_id(holder, datatype)
+            .map(|c| c.iter().flat_map(|v| v.entities()).filter(|e| *e != Self::NIL).collect())
+            .unwrap_or_default()
+    }
+
+    fn index_references(&mut self, holder: EntityId, datatype: DatatypeId) {
+        for entity in self.component_references(holder, datatype) {
+            self.referrers.entry(entity).or_default().insert((holder, datatype));
+        }
+    }
+
+    fn unindex_references(&mut self, holder: EntityId, datatype: DatatypeId) {
+        for entity in self.component_references(holder, datatype) {
+            if let Some(referrers) = self.referrers.get_mut(&entity) {
+                referrers.remove(&(holder, datatype));
+                if referrers.is_empty() {
+                    self.referrers.remove(&entity);
+                }
+            }
+        }
+    }
+
+    /*
+        Entities whose components have a field referring to the entity,
+        in handle order
+     */
+    pub fn referrers(&self, entity: EntityId) -> Vec<EntityId> {
+        let mut holders = self.referrers.get(&entity).into_iter().flatten()
+            .map(|(holder, _)| *holder)
+            .collect::<Vec<_>>();
+        holders.sort();
+        holders.dedup();
+        holders
+    }
+
+    pub fn try_remove_component(&mut self, entity: EntityId, name: &str) -> Result<(), WeaveError> {
+        self.check_valid(entity)?;
+        if !self.has_component(entity, name) {
+            return Err(WeaveError::ComponentMissing { entity, datatype: name.to_string() });
         }
+
+        self.remove_component(entity, name);
+        Ok(())
     }
 
     pub fn remove_component(&mut self, entity: EntityId, name: &str) {
-        let id = Self::get_type_id(name);
+        let Some(id) = self.resolve_type_id(name) else {
+            return;
+        };
+        self.unindex_references(entity, id);
         if let Some(attachments) = self.data.get_mut(&id) {
-            attachments.remove(&entity);
+            attachments.remove(entity, &mut self.strings);
             if let Some(archetypes) = self.archetypes.get_vec_mut(&entity) {
                 if let Some(index) = archetypes.iter().position(|e| *e == id) {
                     archetypes.remove(index);
                 }
+                if archetypes.is_empty() {
+                    self.archetypes.remove(&entity);
+                }
             }
         }
     }
@@ -427,4 +1470,51 @@ impl Weave {
             vec![]
         }
     }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.identities.iter().filter(|&e| *e != Self::NIL).copied()
+    }
+
+    pub fn components_of(&self, entity: EntityId) -> Vec<String> {
+        self.get_archetype(entity).iter()
+            .filter_map(|id| self.type_names.get(id).cloned())
+            .collect()
+    }
+
+    /*
+        Entities having every one of the named components, in handle order
+     */
+    pub fn entities_with(&self, names: &[&str]) -> Vec<EntityId> {
+        let mut stores = vec![];
+        for name in names {
+            match self.resolve_type_id(name).and_then(|id| self.data.get(&id)) {
+                Some(store) => stores.push(store),
+                None => return vec![],
+            }
+        }
+
+        let mut entities = match stores.iter().min_by_key(|s| s.entities.len()) {
+            Some(smallest) => smallest.entities.iter()
+                .filter(|&e| stores.iter().all(|s| s.contains(*e)))
+                .copied().collect::<Vec<_>>(),
+            None => self.entities().collect(),
+        };
+        entities.sort();
+        entities
+    }
+
+    /*
+        Entities having none of the named components, in handle order
+     */
+    pub fn entities_without(&self, names: &[&str]) -> Vec<EntityId> {
+        let stores = names.iter()
+            .filter_map(|name| self.resolve_type_id(name).and_then(|id| self.data.get(&id)))
+            .collect::<Vec<_>>();
+
+        let mut entities = self.entities()
+            .filter(|&e| stores.iter().all(|s| !s.contains(e)))
+            .collect::<Vec<_>>();
+        entities.sort();
+        entities
+    }
 }
@@ -1,35 +1,153 @@
+use std::collections::HashMap;
 use std::ffi::{c_char, c_void, CStr, CString};
 use std::slice;
-use crate::core::{DataField, DataValue, Datatype, EntityId, Weave};
+use std::time::Duration;
+use crate::core::{DataField, DataValue, Datatype, EntityId, RefPolicy, Weave};
 use crate::io;
+use crate::path::Path;
 use crate::replace::replace;
-use crate::search::{find_all, find_one};
+use crate::search::{find_all_with, find_one_with, matches_with, MatchOptions, Matches, StopReason};
 use crate::traverse::{arrows, arrows_in, arrows_out, external_deps, down, down_n, marks, next, next_n, prev, prev_n, tethers, to_src, to_tgt, up, up_n};
+use crate::traverse::{arrows_in_iter, arrows_iter, arrows_out_iter, down_iter, external_deps_iter, marks_iter, next_iter, prev_iter, tethers_iter, to_src_iter, to_tgt_iter, up_iter};
 use crate::shape::{connect, hoist, lift, lower, parent, pivot};
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WvDatatypeKind {
+    Entity,
+    Int,
+    Float,
+    Bool,
+    String,
+    Bytes,
+    List,
+    Optional,
+    Enum,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+/*
+    C-side description of a `Datatype`: `item` describes the items of a
+    list or optional and `name` names an enum; both are null otherwise
+ */
+#[repr(C)]
+pub struct WvDatatype {
+    kind: WvDatatypeKind,
+    item: *const WvDatatype,
+    name: *const c_char,
+}
+
+impl WvDatatype {
+    pub fn parse(datatype: Datatype) -> Self {
+        let (kind, item, name) = match datatype {
+            Datatype::Entity => (WvDatatypeKind::Entity, None, None),
+            Datatype::Int => (WvDatatypeKind::Int, None, None),
+            Datatype::Float => (WvDatatypeKind::Float, None, None),
+            Datatype::Bool => (WvDatatypeKind::Bool, None, None),
+            Datatype::String => (WvDatatypeKind::String, None, None),
+            Datatype::Bytes => (WvDatatypeKind::Bytes, None, None),
+            Datatype::List(item) => (WvDatatypeKind::List, Some(*item), None),
+            Datatype::Optional(item) => (WvDatatypeKind::Optional, Some(*item), None),
+            Datatype::Enum(name) => (WvDatatypeKind::Enum, None, Some(name)),
+            Datatype::Vec2 => (WvDatatypeKind::Vec2, None, None),
+            Datatype::Vec3 => (WvDatatypeKind::Vec3, None, None),
+            Datatype::Vec4 => (WvDatatypeKind::Vec4, None, None),
+        };
+
+        WvDatatype {
+            kind,
+            item: item.map_or(std::ptr::null(), |t| Box::into_raw(Box::new(WvDatatype::parse(t)))),
+            name: name.map_or(std::ptr::null(), |n| CString::new(n).unwrap().into_raw()),
+        }
+    }
+
+    /*
+        Frees what `parse` allocated
+     */
+    fn free(self) {
+        if !self.item.is_null() {
+            unsafe { Box::from_raw(self.item as *mut WvDatatype) }.free();
+        }
+        if !self.name.is_null() {
+            drop(unsafe { CString::from_raw(self.name as *mut c_char) });
+        }
+    }
+}
+
+impl From<&WvDatatype> for Datatype {
+    fn from(value: &WvDatatype) -> Self {
+        let item = || Box::new(Datatype::from(unsafe { value.item.as_ref() }.expect("item datatype missing")));
+        match value.kind {
+            WvDatatypeKind::Entity => Datatype::Entity,
+            WvDatatypeKind::Int => Datatype::Int,
+            WvDatatypeKind::Float => Datatype::Float,
+            WvDatatypeKind::Bool => Datatype::Bool,
+            WvDatatypeKind::String => Datatype::String,
+            WvDatatypeKind::Bytes => Datatype::Bytes,
+            WvDatatypeKind::List => Datatype::List(item()),
+            WvDatatypeKind::Optional => Datatype::Optional(item()),
+            WvDatatypeKind::Enum => {
+                let cstr = unsafe { CStr::from_ptr(value.name) }.to_str().expect("CString to_str failed");
+                Datatype::Enum(cstr.to_string())
+            }
+            WvDatatypeKind::Vec2 => Datatype::Vec2,
+            WvDatatypeKind::Vec3 => Datatype::Vec3,
+            WvDatatypeKind::Vec4 => Datatype::Vec4,
+        }
+    }
+}
+
+/*
+    `default` points at the default value, laid out as for
+    `wv_add_component`, or is null when the field has none
+ */
 #[repr(C)]
 pub struct WvDataField {
     name: *const c_char,
-    datatype: Datatype,
+    datatype: WvDatatype,
+    default: *const c_void,
+    on_delete: RefPolicy,
 }
 
 impl WvDataField {
-    pub fn parse(value: DataField) -> Self {
+    pub fn parse(wv: &Weave, value: DataField) -> Self {
         WvDataField {
             name: CString::new(value.name).unwrap().into_raw(),
-            datatype: value.datatype,
+            default: value.default.as_ref().map_or(std::ptr::null(), |v| write_field_value(wv, v)),
+            datatype: WvDatatype::parse(value.datatype),
+            on_delete: value.on_delete,
         }
     }
-}
 
-impl Into<DataField> for WvDataField {
-    fn into(self) -> DataField {
-        let cstr = unsafe { CStr::from_ptr(self.name) }.to_str().expect("CString to_str failed");
-        DataField {
-            name: cstr.to_string(),
-            datatype: self.datatype,
+    /*
+        Frees what `parse` allocated
+     */
+    fn free(self) {
+        free_field_value(&Datatype::from(&self.datatype), self.default);
+        if !self.name.is_null() {
+            drop(unsafe { CString::from_raw(self.name as *mut c_char) });
         }
+        self.datatype.free();
     }
+
+    fn read(&self, wv: &Weave) -> DataField {
+        let cstr = unsafe { CStr::from_ptr(self.name) }.to_str().expect("CString to_str failed");
+        let datatype = Datatype::from(&self.datatype);
+        let default = (!self.default.is_null()).then(|| read_field_value(wv, &datatype, self.default));
+        DataField { name: cstr.to_string(), datatype, default, on_delete: self.on_delete }
+    }
+}
+
+/*
+    How list fields are passed in: `items` points at `len` pointers to
+    the item values
+ */
+#[repr(C)]
+pub struct WvList {
+    pub len: usize,
+    pub items: *const *const c_void,
 }
 
 #[no_mangle]
@@ -52,62 +170,62 @@ extern "C" fn wv_new_knot(wv: &mut Weave) -> usize {
 
 #[no_mangle]
 extern "C" fn wv_new_arrow(wv: &mut Weave, src: usize, tgt: usize) -> usize {
-    (&mut *wv).new_arrow(src, tgt)
+    wv.try_new_arrow(src, tgt).unwrap_or(NIL)
 }
 
 #[no_mangle]
 extern "C" fn wv_new_mark(wv: &mut Weave, tgt: usize) -> usize {
-    (&mut *wv).new_mark(tgt)
+    wv.try_new_mark(tgt).unwrap_or(NIL)
 }
 
 #[no_mangle]
 extern "C" fn wv_new_tether(wv: &mut Weave, src: usize) -> usize {
-    (&mut *wv).new_tether(src)
+    wv.try_new_tether(src).unwrap_or(NIL)
 }
 
 #[no_mangle]
 extern "C" fn wv_src(wv: &Weave, id: usize) -> usize {
-    (&*wv).src(id)
+    wv.try_src(id).unwrap_or(NIL)
 }
 
 #[no_mangle]
 extern "C" fn wv_tgt(wv: &Weave, id: usize) -> usize {
-    (&*wv).tgt(id)
+    wv.try_tgt(id).unwrap_or(NIL)
 }
 
 #[no_mangle]
-extern "C" fn wv_change_src(wv: &mut Weave, id: usize, src: usize) {
-    (&mut *wv).change_src(id, src)
+extern "C" fn wv_change_src(wv: &mut Weave, id: usize, src: usize) -> bool {
+    wv.try_change_src(id, src).is_ok()
 }
 
 #[no_mangle]
-extern "C" fn wv_change_tgt(wv: &mut Weave, id: usize, tgt: usize) {
-    (&mut *wv).change_tgt(id, tgt)
+extern "C" fn wv_change_tgt(wv: &mut Weave, id: usize, tgt: usize) -> bool {
+    wv.try_change_tgt(id, tgt).is_ok()
 }
 
 #[no_mangle]
-extern "C" fn wv_change_ends(wv: &mut Weave, id: usize, src: usize, tgt: usize) {
-    (&mut *wv).change_ends(id, src, tgt)
+extern "C" fn wv_change_ends(wv: &mut Weave, id: usize, src: usize, tgt: usize) -> bool {
+    wv.try_change_ends(id, src, tgt).is_ok()
 }
 
 #[no_mangle]
 extern "C" fn wv_is_knot(wv: &Weave, id: usize) -> bool {
-    (&*wv).is_knot(id)
+    wv.try_is_knot(id).unwrap_or(false)
 }
 
 #[no_mangle]
 extern "C" fn wv_is_arrow(wv: &Weave, id: usize) -> bool {
-    (&*wv).is_arrow(id)
+    wv.try_is_arrow(id).unwrap_or(false)
 }
 
 #[no_mangle]
 extern "C" fn wv_is_mark(wv: &Weave, id: usize) -> bool {
-    (&*wv).is_mark(id)
+    wv.try_is_mark(id).unwrap_or(false)
 }
 
 #[no_mangle]
 extern "C" fn wv_is_tether(wv: &Weave, id: usize) -> bool {
-    (&*wv).is_tether(id)
+    wv.try_is_tether(id).unwrap_or(false)
 }
 
 #[no_mangle]
@@ -121,30 +239,62 @@ extern "C" fn wv_is_nil(wv: &Weave, id: usize) -> bool {
 }
 
 #[no_mangle]
-extern "C" fn wv_delete_cascade(wv: &mut Weave, id: &mut usize) {
-    (&mut *wv).delete_cascade(*id);
+extern "C" fn wv_is_stale(wv: &Weave, id: usize) -> bool {
+    wv.is_stale(id)
+}
+
+#[no_mangle]
+extern "C" fn wv_entity_index(id: usize) -> usize {
+    Weave::entity_index(id)
+}
+
+#[no_mangle]
+extern "C" fn wv_entity_generation(id: usize) -> usize {
+    Weave::entity_generation(id)
+}
+
+#[no_mangle]
+extern "C" fn wv_delete_cascade(wv: &mut Weave, id: &mut usize) -> bool {
+    if wv.is_valid(*id) && wv.try_delete_cascade(*id).is_err() {
+        return false;
+    }
     *id = NIL;
+    true
 }
 
 #[no_mangle]
-extern "C" fn wv_delete_orphan(wv: &mut Weave, id: &mut usize) {
-    (&mut *wv).delete_orphan(*id);
+extern "C" fn wv_delete_orphan(wv: &mut Weave, id: &mut usize) -> bool {
+    if wv.is_valid(*id) && wv.try_delete_orphan(*id).is_err() {
+        return false;
+    }
     *id = NIL;
+    true
 }
 
 #[no_mangle]
 extern "C" fn wv_def_data(wv: &mut Weave, name: *const c_char, datatype: *const WvDataField, len: usize) -> u64 {
     let fields: Vec<DataField> = unsafe { slice::from_raw_parts(datatype, len) }
-        .iter().map(|v| {
-        let cstr = unsafe { CStr::from_ptr(v.name.clone()) }.to_str().expect("CString to_str failed");
-        DataField {
-            name: cstr.to_string(),
-            datatype: v.datatype.clone(),
-        }
-    }).collect();
+        .iter().map(|v| v.read(wv)).collect();
 
     let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
-    (&mut *wv).def_datatype(cstr, fields.as_slice())
+    wv.try_def_datatype(cstr, fields.as_slice()).unwrap_or(NIL as u64)
+}
+
+#[no_mangle]
+extern "C" fn wv_def_enum(wv: &mut Weave, name: *const c_char, len: usize, variants: *const *const c_char) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    wv.try_def_enum(cstr, &read_names(len, variants)).is_ok()
+}
+
+#[no_mangle]
+extern "C" fn wv_undef_data(wv: &mut Weave, name: *const c_char) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    wv.try_undef_datatype(cstr).is_ok()
+}
+
+#[no_mangle]
+extern "C" fn wv_set_strict_data(wv: &mut Weave, strict: bool) {
+    wv.set_strict_datatypes(strict);
 }
 
 #[no_mangle]
@@ -162,30 +312,179 @@ extern "C" fn wv_get_data_field_count(wv: &Weave, name: *const c_char) -> usize
 #[no_mangle]
 extern "C" fn wv_get_data_field(wv: &Weave, name: *const c_char, index: usize) -> WvDataField {
     let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
-    WvDataField::parse((&*wv).get_datatype_field(cstr, index))
+    match wv.try_get_datatype_field(cstr, index) {
+        Ok(field) => WvDataField::parse(wv, field),
+        Err(_) => WvDataField { name: std::ptr::null(), datatype: WvDatatype::parse(Datatype::Entity), default: std::ptr::null(), on_delete: RefPolicy::Nullify },
+    }
 }
 
+/*
+    Frees a field handed out by `wv_get_data_field`
+ */
 #[no_mangle]
-extern "C" fn wv_add_component(wv: &mut Weave, entity: usize, name: *const c_char, fields: *const *const c_void) {
-    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
-    let count = wv.get_datatype_field_count(cstr);
-    let fields = unsafe { slice::from_raw_parts(fields, count) }.iter().cloned().collect::<Vec<_>>();
-    let mut values = vec![];
-    for i in 0..count {
-        let df = wv.get_datatype_field(cstr, i);
-        let value = match df.datatype {
-            Datatype::Entity => DataValue::Entity(unsafe { *(fields[i] as *const usize).as_ref().unwrap() }),
-            Datatype::Int => DataValue::Int(unsafe { *(fields[i] as *const i64).as_ref().unwrap() }),
-            Datatype::Float => DataValue::Float(unsafe { *(fields[i] as *const f64).as_ref().unwrap() }),
-            Datatype::Bool => DataValue::Bool(unsafe { *(fields[i] as *const bool).as_ref().unwrap() }),
-            Datatype::String => {
-                let v = unsafe { CStr::from_ptr(fields[i] as *const c_char) }.to_str().expect("CString to_str failed");
-                DataValue::String(v.to_string())
+extern "C" fn wv_free_data_field(field: WvDataField) {
+    field.free();
+}
+
+/*
+    Bytes are passed as a `WvByteArray`, lists as a `WvList`, enums as the
+    u32 index of the variant and vectors as consecutive doubles. A null
+    pointer is an empty optional.
+ */
+fn read_field_value(wv: &Weave, datatype: &Datatype, field: *const c_void) -> DataValue {
+    match datatype {
+        Datatype::Entity => DataValue::Entity(unsafe { *(field as *const usize).as_ref().unwrap() }),
+        Datatype::Int => DataValue::Int(unsafe { *(field as *const i64).as_ref().unwrap() }),
+        Datatype::Float => DataValue::Float(unsafe { *(field as *const f64).as_ref().unwrap() }),
+        Datatype::Bool => DataValue::Bool(unsafe { *(field as *const bool).as_ref().unwrap() }),
+        Datatype::String => {
+            let v = unsafe { CStr::from_ptr(field as *const c_char) }.to_str().expect("CString to_str failed");
+            DataValue::String(v.to_string())
+        }
+        Datatype::Bytes => {
+            let bytes = unsafe { (field as *const WvByteArray).as_ref().unwrap() };
+            DataValue::Bytes(unsafe { slice::from_raw_parts(bytes.ptr, bytes.len) }.to_vec())
+        }
+        Datatype::List(item) => {
+            let list = unsafe { (field as *const WvList).as_ref().unwrap() };
+            let items = unsafe { slice::from_raw_parts(list.items, list.len) };
+            DataValue::List(*item.clone(), items.iter().map(|v| read_field_value(wv, item, *v)).collect())
+        }
+        Datatype::Optional(item) => {
+            let value = (!field.is_null()).then(|| Box::new(read_field_value(wv, item, field)));
+            DataValue::Optional(*item.clone(), value)
+        }
+        Datatype::Enum(name) => {
+            let index = unsafe { *(field as *const u32).as_ref().unwrap() } as usize;
+            let variant = wv.get_enum_variants(name).and_then(|v| v.get(index)).cloned().unwrap_or_default();
+            DataValue::Enum(name.clone(), variant)
+        }
+        Datatype::Vec2 => DataValue::Vec2(unsafe { *(field as *const [f64; 2]).as_ref().unwrap() }),
+        Datatype::Vec3 => DataValue::Vec3(unsafe { *(field as *const [f64; 3]).as_ref().unwrap() }),
+        Datatype::Vec4 => DataValue::Vec4(unsafe { *(field as *const [f64; 4]).as_ref().unwrap() }),
+    }
+}
+
+/*
+    The reverse of `read_field_value`; the value is leaked the same way
+    field names handed to C are
+ */
+fn write_field_value(wv: &Weave, value: &DataValue) -> *const c_void {
+    fn leak<T>(value: T) -> *const c_void {
+        Box::into_raw(Box::new(value)) as *const c_void
+    }
+
+    match value {
+        DataValue::Entity(e) => leak(*e),
+        DataValue::Int(i) => leak(*i),
+        DataValue::Float(f) => leak(*f),
+        DataValue::Bool(b) => leak(*b),
+        DataValue::String(s) => CString::new(s.as_str()).unwrap().into_raw() as *const c_void,
+        DataValue::Bytes(b) => leak::<WvByteArray>(b.clone().into()),
+        DataValue::List(_, items) => {
+            let items = items.iter().map(|v| write_field_value(wv, v)).collect::<Vec<_>>();
+            leak(WvList { len: items.len(), items: Box::into_raw(items.into_boxed_slice()) as *const *const c_void })
+        }
+        DataValue::Optional(_, item) => item.as_ref().map_or(std::ptr::null(), |v| write_field_value(wv, v)),
+        DataValue::Enum(name, variant) => {
+            let index = wv.get_enum_variants(name).and_then(|v| v.iter().position(|n| n == variant)).unwrap_or(0);
+            leak(index as u32)
+        }
+        DataValue::Vec2(v) => leak(*v),
+        DataValue::Vec3(v) => leak(*v),
+        DataValue::Vec4(v) => leak(*v),
+    }
+}
+
+/*
+    Frees a value made by `write_field_value`
+ */
+fn free_field_value(datatype: &Datatype, value: *const c_void) {
+    unsafe fn free<T>(value: *const c_void) {
+        drop(Box::from_raw(value as *mut T));
+    }
+
+    if value.is_null() {
+        return;
+    }
+
+    unsafe {
+        match datatype {
+            Datatype::Entity => free::<usize>(value),
+            Datatype::Int => free::<i64>(value),
+            Datatype::Float => free::<f64>(value),
+            Datatype::Bool => free::<bool>(value),
+            Datatype::String => drop(CString::from_raw(value as *mut c_char)),
+            Datatype::Bytes => {
+                let bytes = Box::from_raw(value as *mut WvByteArray);
+                drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(bytes.ptr as *mut u8, bytes.len)));
             }
+            Datatype::List(item) => {
+                let list = Box::from_raw(value as *mut WvList);
+                let items = Box::from_raw(std::ptr::slice_from_raw_parts_mut(list.items as *mut *const c_void, list.len));
+                items.iter().for_each(|v| free_field_value(item, *v));
+            }
+            Datatype::Optional(item) => free_field_value(item, value),
+            Datatype::Enum(_) => free::<u32>(value),
+            Datatype::Vec2 => free::<[f64; 2]>(value),
+            Datatype::Vec3 => free::<[f64; 3]>(value),
+            Datatype::Vec4 => free::<[f64; 4]>(value),
+        }
+    }
+}
+
+fn read_component_values(wv: &Weave, name: &str, fields: *const *const c_void) -> Vec<DataValue> {
+    let count = wv.get_datatype_field_count(name);
+    let fields = unsafe { slice::from_raw_parts(fields, count) };
+    fields.iter().enumerate()
+        .map(|(i, field)| read_field_value(wv, &wv.get_datatype_field(name, i).datatype, *field))
+        .collect()
+}
+
+#[no_mangle]
+extern "C" fn wv_add_component(wv: &mut Weave, entity: usize, name: *const c_char, fields: *const *const c_void) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    let values = read_component_values(wv, cstr, fields);
+    wv.try_add_component(entity, cstr, &values).is_ok()
+}
+
+/*
+    Adds a component from `len` field names and pointers to their values;
+    fields left out take their defaults
+ */
+#[no_mangle]
+extern "C" fn wv_add_component_named(wv: &mut Weave, entity: usize, name: *const c_char, len: usize,
+                                     field_names: *const *const c_char, fields: *const *const c_void) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    let names = read_names(len, field_names);
+    let fields = unsafe { slice::from_raw_parts(fields, len) };
+    let mut named = vec![];
+    for (field_name, field) in names.into_iter().zip(fields) {
+        let Ok(index) = wv.try_get_datatype_field_index(cstr, field_name) else {
+            return false;
         };
-        values.push(value.clone());
+        named.push((field_name, read_field_value(wv, &wv.get_datatype_field(cstr, index).datatype, *field)));
     }
-    (&mut *wv).add_component(entity, cstr, &values);
+
+    wv.try_add_component_named(entity, cstr, &named).is_ok()
+}
+
+#[no_mangle]
+extern "C" fn wv_set_component(wv: &mut Weave, entity: usize, name: *const c_char, fields: *const *const c_void) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    let values = read_component_values(wv, cstr, fields);
+    wv.try_set_component(entity, cstr, &values).is_ok()
+}
+
+#[no_mangle]
+extern "C" fn wv_update_component_field(wv: &mut Weave, entity: usize, name: *const c_char, field_name: *const c_char, value: *const c_void) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    let field_cstr = unsafe { CStr::from_ptr(field_name) }.to_str().expect("CString to_str failed");
+    let Ok(index) = wv.try_get_datatype_field_index(cstr, field_cstr) else {
+        return false;
+    };
+    let value = read_field_value(wv, &wv.get_datatype_field(cstr, index).datatype, value);
+    wv.try_update_component_field(entity, cstr, field_cstr, value).is_ok()
 }
 
 #[no_mangle]
@@ -194,23 +493,60 @@ extern "C" fn wv_has_component(wv: &Weave, entity: usize, name: *const c_char) -
     (&*wv).has_component(entity, cstr)
 }
 
+/*
+    Points into the weave's component storage rather than at a copy. The
+    pointer is only valid until the weave next changes (any component
+    added, set, updated or removed, or any datatype defined), so read the
+    value before then. The same goes for `wv_get_component_field_item`
+    and `wv_get_component_field_named`.
+ */
 #[no_mangle]
 extern "C" fn wv_get_component_field(wv: &Weave, entity: usize, name: *const c_char, index: usize) -> *const c_void {
     let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
-    let v = (&*wv).get_component(entity, cstr);
-    match &v[index] {
-        DataValue::Entity(e) => e as *const _ as *const c_void,
-        DataValue::Int(i) => i as *const _ as *const c_void,
-        DataValue::Float(f) => f as *const _ as *const c_void,
-        DataValue::Bool(b) => b as *const _ as *const c_void,
-        DataValue::String(s) => CString::new(s.clone()).unwrap().into_raw() as *const c_void,
+    match wv.try_get_component(entity, cstr) {
+        Ok(component) => component.get_ptr(index).map_or(std::ptr::null(), |p| p as *const c_void),
+        Err(_) => std::ptr::null(),
+    }
+}
+
+/*
+    Number of items of a bytes, list or optional field
+ */
+#[no_mangle]
+extern "C" fn wv_get_component_field_len(wv: &Weave, entity: usize, name: *const c_char, index: usize) -> usize {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    match wv.try_get_component(entity, cstr) {
+        Ok(component) => component.get_len(index).unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+#[no_mangle]
+extern "C" fn wv_get_component_field_item(wv: &Weave, entity: usize, name: *const c_char, index: usize, item: usize) -> *const c_void {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    match wv.try_get_component(entity, cstr) {
+        Ok(component) => component.get_item_ptr(index, item).map_or(std::ptr::null(), |p| p as *const c_void),
+        Err(_) => std::ptr::null(),
     }
 }
 
 #[no_mangle]
-extern "C" fn wv_remove_component(wv: &mut Weave, entity: usize, name: *const c_char) {
+extern "C" fn wv_get_component_field_named(wv: &Weave, entity: usize, name: *const c_char, field_name: *const c_char) -> *const c_void {
     let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
-    (&mut *wv).remove_component(entity, cstr);
+    let field_cstr = unsafe { CStr::from_ptr(field_name) }.to_str().expect("CString to_str failed");
+    let Ok(index) = wv.try_get_datatype_field_index(cstr, field_cstr) else {
+        return std::ptr::null();
+    };
+    match wv.try_get_component(entity, cstr) {
+        Ok(component) => component.get_ptr(index).map_or(std::ptr::null(), |p| p as *const c_void),
+        Err(_) => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+extern "C" fn wv_remove_component(wv: &mut Weave, entity: usize, name: *const c_char) -> bool {
+    let cstr = unsafe { CStr::from_ptr(name) }.to_str().expect("CString to_str failed");
+    wv.try_remove_component(entity, cstr).is_ok()
 }
 
 
@@ -281,6 +617,41 @@ impl Into<WvByteArray> for Vec<u8> {
     }
 }
 
+fn read_names<'a>(len: usize, names: *const *const c_char) -> Vec<&'a str> {
+    unsafe { slice::from_raw_parts(names, len) }.iter()
+        .map(|n| unsafe { CStr::from_ptr(*n) }.to_str().expect("CString to_str failed"))
+        .collect()
+}
+
+#[no_mangle]
+extern "C" fn wv_entities_with(wv: &Weave, len: usize, names: *const *const c_char) -> WvEntityArray {
+    wv.entities_with(&read_names(len, names)).into()
+}
+
+#[no_mangle]
+extern "C" fn wv_referrers(wv: &Weave, entity: usize) -> WvEntityArray {
+    wv.referrers(entity).into()
+}
+
+/*
+    Runs a path expression (see `path::Path`) from the given entities.
+    A malformed path gives an empty array with a null `ptr`.
+ */
+#[no_mangle]
+extern "C" fn wv_query(wv: &Weave, len: usize, it: *const usize, path: *const c_char) -> WvEntityArray {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    let path = unsafe { CStr::from_ptr(path) }.to_str().expect("CString to_str failed");
+    match Path::parse(path) {
+        Ok(path) => path.eval(wv, it).into(),
+        Err(_) => WvEntityArray { len: 0, ptr: std::ptr::null() },
+    }
+}
+
+#[no_mangle]
+extern "C" fn wv_entities_without(wv: &Weave, len: usize, names: *const *const c_char) -> WvEntityArray {
+    wv.entities_without(&read_names(len, names)).into()
+}
+
 #[no_mangle]
 extern "C" fn wv_move__deps(wv: &mut Weave, len: usize, it: *const usize) -> WvEntityArray {
     let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
@@ -373,17 +744,130 @@ extern "C" fn wv_move__up_n(wv: &mut Weave, len: usize, it: *const usize) -> WvE
     up_n(&*wv, it).into()
 }
 
+/*
+    The `_into` moves write into a buffer owned by the caller instead of
+    allocating. They return how many entities the move produced; when that
+    is more than `cap`, only the first `cap` were written.
+ */
+fn fill_buffer(items: impl Iterator<Item = EntityId>, cap: usize, out: *mut usize) -> usize {
+    let out: &mut [usize] = if out.is_null() { &mut [] } else { unsafe { slice::from_raw_parts_mut(out, cap) } };
+    let mut count = 0;
+    for item in items {
+        if let Some(slot) = out.get_mut(count) {
+            *slot = item;
+        }
+        count += 1;
+    }
+    count
+}
+
+#[no_mangle]
+extern "C" fn wv_move__deps_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(external_deps_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__arrows_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(arrows_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__arrows_in_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(arrows_in_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__arrows_out_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(arrows_out_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__marks_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(marks_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__tethers_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(tethers_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__to_src_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(to_src_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__to_tgt_into(wv: &Weave, len: usize, it: *const usize, cap: usize, out: *mut usize) -> usize {
+    let it: &[usize] = unsafe { slice::from_raw_parts(it, len) };
+    fill_buffer(to_tgt_iter(wv, it.iter().copied()), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__prev_into(wv: &Weave, it: usize, cap: usize, out: *mut usize) -> usize {
+    fill_buffer(prev_iter(wv, it), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__next_into(wv: &Weave, it: usize, cap: usize, out: *mut usize) -> usize {
+    fill_buffer(next_iter(wv, it), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__down_into(wv: &Weave, it: usize, cap: usize, out: *mut usize) -> usize {
+    fill_buffer(down_iter(wv, it), cap, out)
+}
+
+#[no_mangle]
+extern "C" fn wv_move__up_into(wv: &Weave, it: usize, cap: usize, out: *mut usize) -> usize {
+    fill_buffer(up_iter(wv, it), cap, out)
+}
+
+#[repr(C)]
+pub struct WvMatchOptions {
+    pub injective: bool,
+    pub induced: bool,
+}
+
+impl From<WvMatchOptions> for MatchOptions {
+    fn from(value: WvMatchOptions) -> Self {
+        MatchOptions { injective: value.injective, induced: value.induced }
+    }
+}
+
+/*
+    Appends the solution as pattern/target pairs, in pattern id order
+ */
+fn push_solution(key_values: &mut Vec<usize>, solution: HashMap<EntityId, EntityId>) {
+    let mut solution = solution.into_iter().collect::<Vec<_>>();
+    solution.sort_unstable();
+    for (k, v) in solution {
+        key_values.push(k);
+        key_values.push(v);
+    }
+}
+
 #[no_mangle]
 extern "C" fn wv_search__find_one(wv: &Weave, hoisted_pattern: usize, hoisted_target: usize, size: &mut usize, count: &mut usize) -> WvEntityArray {
-    let result = find_one(wv, hoisted_pattern, hoisted_target);
+    let options = WvMatchOptions { injective: true, induced: false };
+    wv_search__find_one_with(wv, hoisted_pattern, hoisted_target, options, size, count)
+}
+
+#[no_mangle]
+extern "C" fn wv_search__find_one_with(wv: &Weave, hoisted_pattern: usize, hoisted_target: usize, options: WvMatchOptions,
+                                       size: &mut usize, count: &mut usize) -> WvEntityArray {
+    let result = find_one_with(wv, hoisted_pattern, hoisted_target, options.into());
     if let Some(hash) = result {
         *count = 1;
         *size = hash.len();
         let mut key_values = vec![];
-        for (k, v) in hash {
-            key_values.push(k);
-            key_values.push(v);
-        }
+        push_solution(&mut key_values, hash);
         key_values.into()
     } else {
         *count = 0;
@@ -393,21 +877,117 @@ extern "C" fn wv_search__find_one(wv: &Weave, hoisted_pattern: usize, hoisted_ta
 
 #[no_mangle]
 extern "C" fn wv_search__find_all(wv: &Weave, hoisted_pattern: usize, hoisted_target: usize, size: &mut usize, count: &mut usize) -> WvEntityArray {
-    let result = find_all(wv, hoisted_pattern, hoisted_target);
+    let options = WvMatchOptions { injective: true, induced: false };
+    wv_search__find_all_with(wv, hoisted_pattern, hoisted_target, options, size, count)
+}
+
+#[no_mangle]
+extern "C" fn wv_search__find_all_with(wv: &Weave, hoisted_pattern: usize, hoisted_target: usize, options: WvMatchOptions,
+                                       size: &mut usize, count: &mut usize) -> WvEntityArray {
+    let result = find_all_with(wv, hoisted_pattern, hoisted_target, options.into());
     *count = result.len();
     let mut key_values = vec![];
 
     for solution in result {
         *size = solution.len();
-        for (k, v) in solution {
-            key_values.push(k);
-            key_values.push(v);
-        }
+        push_solution(&mut key_values, solution);
     }
 
     key_values.into()
 }
 
+/*
+    A search handing out one match at a time (see `search::Matches`).
+    The weave must outlive the cursor and stay unchanged while it's open.
+ */
+pub struct WvSearchCursor {
+    matches: Matches<'static>,
+    solution: Vec<usize>,
+}
+
+/*
+    Limits on a search; 0 means no limit
+ */
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WvMatchLimits {
+    pub max_count: usize,
+    pub max_steps: usize,
+    pub timeout_ms: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WvStopReason {
+    None,
+    Count,
+    Steps,
+    Timeout,
+    Cancelled,
+}
+
+/*
+    Starts a search. `cancel`, if not null, is called with `user_data`
+    before every step, and stops the search by returning true.
+ */
+#[no_mangle]
+extern "C" fn wv_search__begin(wv: *const Weave, hoisted_pattern: usize, hoisted_target: usize, options: WvMatchOptions,
+                               limits: WvMatchLimits, cancel: Option<extern "C" fn(*mut c_void) -> bool>,
+                               user_data: *mut c_void) -> *mut WvSearchCursor {
+    let wv = unsafe { &*wv };
+    let mut matches = matches_with(wv, hoisted_pattern, hoisted_target, options.into());
+    if limits.max_count > 0 {
+        matches = matches.max_count(limits.max_count);
+    }
+    if limits.max_steps > 0 {
+        matches = matches.max_steps(limits.max_steps);
+    }
+    if limits.timeout_ms > 0 {
+        matches = matches.time_limit(Duration::from_millis(limits.timeout_ms));
+    }
+    if let Some(cancel) = cancel {
+        matches = matches.cancel_when(move || cancel(user_data));
+    }
+
+    Box::into_raw(Box::new(WvSearchCursor { matches, solution: vec![] }))
+}
+
+/*
+    Points `solution` at the next match as pattern/target pairs, in
+    pattern id order, and returns true, or returns false once there are
+    no more. The pairs live in the cursor, and are only valid until the
+    next call to `wv_search__next` or `wv_search__end`.
+ */
+#[no_mangle]
+extern "C" fn wv_search__next(cursor: &mut WvSearchCursor, solution: &mut WvEntityArray) -> bool {
+    if let Some(next) = cursor.matches.next() {
+        cursor.solution.clear();
+        push_solution(&mut cursor.solution, next);
+        *solution = WvEntityArray { len: cursor.solution.len(), ptr: cursor.solution.as_ptr() };
+        true
+    } else {
+        false
+    }
+}
+
+#[no_mangle]
+extern "C" fn wv_search__stop_reason(cursor: &WvSearchCursor) -> WvStopReason {
+    match cursor.matches.stop_reason() {
+        None => WvStopReason::None,
+        Some(StopReason::Count) => WvStopReason::Count,
+        Some(StopReason::Steps) => WvStopReason::Steps,
+        Some(StopReason::Timeout) => WvStopReason::Timeout,
+        Some(StopReason::Cancelled) => WvStopReason::Cancelled,
+    }
+}
+
+#[no_mangle]
+extern "C" fn wv_search__end(cursor: *mut WvSearchCursor) {
+    if !cursor.is_null() {
+        drop(unsafe { Box::from_raw(cursor) });
+    }
+}
+
 #[no_mangle]
 extern "C" fn wv_replace__replace(wv: &mut Weave, hoisted_pattern: usize, hoisted_goal: usize, hoisted_target: usize) -> EntityId {
     if let Ok(result) = replace(wv, hoisted_pattern, hoisted_goal, hoisted_target) {
@@ -423,9 +1003,26 @@ extern "C" fn wv_serialize(wv: &mut Weave, id : usize) -> WvByteArray
     io::serialize(wv, id).into()
 }
 
+/*
+    NIL if the data is corrupt, from a newer format or can't be loaded
+ */
 #[no_mangle]
 extern "C" fn wv_deserialize(wv: &mut Weave, len: usize, it: *const u8) -> EntityId
 {
     let it: &[u8] = unsafe { slice::from_raw_parts(it, len) };
-    io::deserialize(wv, it)
+    io::try_deserialize(wv, it).unwrap_or(NIL)
+}
+
+/*
+    An empty array with a null pointer if the data is corrupt or from a
+    newer format
+ */
+#[no_mangle]
+extern "C" fn wv_upgrade(len: usize, it: *const u8) -> WvByteArray
+{
+    let it: &[u8] = unsafe { slice::from_raw_parts(it, len) };
+    match io::try_upgrade(it) {
+        Ok(upgraded) => upgraded.into(),
+        Err(_) => WvByteArray { len: 0, ptr: std::ptr::null() },
+    }
 }
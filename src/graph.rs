@@ -0,0 +1,358 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Entry;
+use crate::core::{EntityId, Weave};
+use crate::storage::DataRef;
+use crate::traverse::{arrows_in_iter, arrows_out_iter, down};
+
+/*
+    Graph algorithms over the weave's arrows. Knots are the nodes and
+    arrows between two of them the edges; with `Scope::Env` only the knots
+    hoisted into the environment count, along with the arrows between them
+    (whether or not those are hoisted too); paths and reachability from a
+    knot outside the scope come out empty. Results come out in id order
+    wherever there is a choice, so they are the same from run to run.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Weave,
+    Env(EntityId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphError {
+    Cycle(Vec<EntityId>),
+    MissingWeight(EntityId),
+    InvalidWeight(EntityId),
+}
+
+struct View<'w> {
+    wv: &'w Weave,
+    nodes: Vec<EntityId>,
+}
+
+impl<'w> View<'w> {
+    fn new(wv: &'w Weave, scope: Scope) -> Self {
+        let mut nodes = match scope {
+            Scope::Weave => wv.entities().filter(|&e| wv.is_knot(e)).collect::<Vec<_>>(),
+            Scope::Env(env) => down(wv, env).into_iter().filter(|&e| wv.is_knot(e)).collect(),
+        };
+        nodes.sort_unstable();
+        View { wv, nodes }
+    }
+
+    fn contains(&self, id: EntityId) -> bool {
+        self.nodes.binary_search(&id).is_ok()
+    }
+
+    fn out_edges(&self, id: EntityId) -> impl Iterator<Item = (EntityId, EntityId)> + '_ {
+        arrows_out_iter(self.wv, [ id ])
+            .map(|arrow| (arrow, self.wv.tgt(arrow)))
+            .filter(|&(_, tgt)| self.contains(tgt))
+    }
+
+    fn successors(&self, id: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+        self.out_edges(id).map(|(_, tgt)| tgt)
+    }
+
+    fn predecessors(&self, id: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+        arrows_in_iter(self.wv, [ id ])
+            .map(|arrow| self.wv.src(arrow))
+            .filter(|&src| self.contains(src))
+    }
+
+    fn reachable(&self, from: EntityId) -> Vec<EntityId> {
+        if !self.contains(from) {
+            return vec![];
+        }
+
+        let mut seen = HashSet::new();
+        let mut unfinished = self.successors(from).collect::<Vec<_>>();
+        while let Some(next) = unfinished.pop() {
+            if seen.insert(next) {
+                unfinished.extend(self.successors(next));
+            }
+        }
+
+        let mut ret = seen.into_iter().collect::<Vec<_>>();
+        ret.sort_unstable();
+        ret
+    }
+}
+
+/*
+    Everything reachable from the entity by following one or more arrows;
+    the entity itself is included only if it lies on a cycle
+ */
+pub fn reachable(wv: &Weave, from: EntityId, scope: Scope) -> Vec<EntityId> {
+    View::new(wv, scope).reachable(from)
+}
+
+pub fn is_reachable(wv: &Weave, from: EntityId, to: EntityId, scope: Scope) -> bool {
+    reachable(wv, from, scope).binary_search(&to).is_ok()
+}
+
+/*
+    The path with the fewest arrows, as the knots along it from `from` to
+    `to`; among equally short paths the one through the lowest ids wins
+ */
+pub fn shortest_path(wv: &Weave, from: EntityId, to: EntityId, scope: Scope) -> Option<Vec<EntityId>> {
+    let view = View::new(wv, scope);
+    if !view.contains(from) {
+        return None;
+    }
+
+    let mut parents = HashMap::from([ (from, from) ]);
+    let mut frontier = VecDeque::from([ from ]);
+    while let Some(next) = frontier.pop_front() {
+        if next == to {
+            return Some(unwind(&parents, from, to));
+        }
+
+        for succ in view.successors(next) {
+            if let Entry::Vacant(e) = parents.entry(succ) {
+                e.insert(next);
+                frontier.push_back(succ);
+            }
+        }
+    }
+
+    None
+}
+
+#[derive(PartialEq)]
+struct Distance(f64, EntityId);
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+fn weight_of(wv: &Weave, arrow: EntityId, weight: &str) -> Result<f64, GraphError> {
+    let component = wv.try_get_component(arrow, weight).map_err(|_| GraphError::MissingWeight(arrow))?;
+    let w = match component.get(0) {
+        Some(DataRef::Int(i)) => i as f64,
+        Some(DataRef::Float(f)) => f,
+        _ => return Err(GraphError::InvalidWeight(arrow)),
+    };
+
+    if w < 0.0 || w.is_nan() {
+        return Err(GraphError::InvalidWeight(arrow));
+    }
+
+    Ok(w)
+}
+
+/*
+    The cheapest path, with each arrow costing the first field of its
+    `weight` component (an Int or a Float, not negative). Returns the
+    total cost along with the knots on the path. Every arrow on the way
+    has to carry a weight.
+ */
+pub fn shortest_path_weighted(wv: &Weave, from: EntityId, to: EntityId, weight: &str, scope: Scope)
+    -> Result<Option<(f64, Vec<EntityId>)>, GraphError> {
+
+    let view = View::new(wv, scope);
+    if !view.contains(from) {
+        return Ok(None);
+    }
+
+    let mut parents = HashMap::from([ (from, from) ]);
+    let mut best = HashMap::from([ (from, 0.0) ]);
+    let mut done = HashSet::new();
+    let mut frontier = BinaryHeap::from([ Reverse(Distance(0.0, from)) ]);
+    while let Some(Reverse(Distance(cost, next))) = frontier.pop() {
+        if !done.insert(next) {
+            continue;
+        }
+
+        if next == to {
+            return Ok(Some((cost, unwind(&parents, from, to))));
+        }
+
+        for (arrow, succ) in view.out_edges(next) {
+            let total = cost + weight_of(wv, arrow, weight)?;
+            if best.get(&succ).is_none_or(|&b| total < b) {
+                best.insert(succ, total);
+                parents.insert(succ, next);
+                frontier.push(Reverse(Distance(total, succ)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn unwind(parents: &HashMap<EntityId, EntityId>, from: EntityId, to: EntityId) -> Vec<EntityId> {
+    let mut path = vec![ to ];
+    let mut at = to;
+    while at != from {
+        at = parents[&at];
+        path.push(at);
+    }
+
+    path.reverse();
+    path
+}
+
+/*
+    Groups of knots that can all reach each other. Each group is sorted
+    and the groups are ordered by their first knot.
+ */
+pub fn strongly_connected(wv: &Weave, scope: Scope) -> Vec<Vec<EntityId>> {
+    // Tarjan's algorithm, with an explicit stack instead of recursion
+    let view = View::new(wv, scope);
+    let mut index = HashMap::new();
+    let mut low = HashMap::new();
+    let mut stack = vec![];
+    let mut on_stack = HashSet::new();
+    let mut components = vec![];
+
+    for &root in &view.nodes {
+        if index.contains_key(&root) {
+            continue;
+        }
+
+        let mut calls = vec![ (root, view.successors(root).collect::<Vec<_>>(), 0) ];
+        index.insert(root, index.len());
+        low.insert(root, index[&root]);
+        stack.push(root);
+        on_stack.insert(root);
+
+        while let Some((node, succs, at)) = calls.last_mut() {
+            let node = *node;
+            if let Some(&succ) = succs.get(*at) {
+                *at += 1;
+                if !index.contains_key(&succ) {
+                    index.insert(succ, index.len());
+                    low.insert(succ, index[&succ]);
+                    stack.push(succ);
+                    on_stack.insert(succ);
+                    calls.push((succ, view.successors(succ).collect(), 0));
+                } else if on_stack.contains(&succ) {
+                    low.insert(node, low[&node].min(index[&succ]));
+                }
+                continue;
+            }
+
+            calls.pop();
+            if let Some((parent, _, _)) = calls.last() {
+                low.insert(*parent, low[parent].min(low[&node]));
+            }
+
+            if low[&node] == index[&node] {
+                let mut component = vec![];
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(&member);
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.sort_unstable();
+                components.push(component);
+            }
+        }
+    }
+
+    components.sort_unstable_by_key(|c| c[0]);
+    components
+}
+
+/*
+    Groups of knots connected by arrows in either direction, sorted the
+    same way as `strongly_connected`
+ */
+pub fn weakly_connected(wv: &Weave, scope: Scope) -> Vec<Vec<EntityId>> {
+    let view = View::new(wv, scope);
+    let mut seen = HashSet::new();
+    let mut components = vec![];
+
+    for &root in &view.nodes {
+        if !seen.insert(root) {
+            continue;
+        }
+
+        let mut component = vec![];
+        let mut unfinished = vec![ root ];
+        while let Some(next) = unfinished.pop() {
+            component.push(next);
+            for neighbour in view.successors(next).chain(view.predecessors(next)) {
+                if seen.insert(neighbour) {
+                    unfinished.push(neighbour);
+                }
+            }
+        }
+        component.sort_unstable();
+        components.push(component);
+    }
+
+    components
+}
+
+/*
+    Orders the knots so every arrow points forward, picking the lowest id
+    whenever several knots are ready. If there is no such order, fails with
+    one of the cycles, as the knots along it starting from the lowest id.
+ */
+pub fn topological_sort(wv: &Weave, scope: Scope) -> Result<Vec<EntityId>, GraphError> {
+    let view = View::new(wv, scope);
+    let mut in_degree = view.nodes.iter()
+        .map(|&n| (n, view.predecessors(n).count()))
+        .collect::<HashMap<_, _>>();
+
+    let mut ready = view.nodes.iter()
+        .filter(|n| in_degree[n] == 0)
+        .map(|&n| Reverse(n))
+        .collect::<BinaryHeap<_>>();
+
+    let mut order = vec![];
+    while let Some(Reverse(next)) = ready.pop() {
+        order.push(next);
+        for succ in view.successors(next) {
+            let d = in_degree.get_mut(&succ).unwrap();
+            *d -= 1;
+            if *d == 0 {
+                ready.push(Reverse(succ));
+            }
+        }
+    }
+
+    if order.len() == view.nodes.len() {
+        return Ok(order);
+    }
+
+    // every knot left over has a predecessor that is left over too, so
+    // walking backwards from any of them has to come round eventually
+    let left = |n: &EntityId| in_degree[n] > 0;
+    let mut path = vec![ *view.nodes.iter().find(|n| left(n)).unwrap() ];
+    loop {
+        let last = *path.last().unwrap();
+        let prev = view.predecessors(last).find(left).unwrap();
+        if let Some(start) = path.iter().position(|&n| n == prev) {
+            let mut cycle = path.split_off(start);
+            cycle.reverse();
+            let lowest = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+            cycle.rotate_left(lowest);
+            return Err(GraphError::Cycle(cycle));
+        }
+        path.push(prev);
+    }
+}
+
+/*
+    For every knot, everything `reachable` from it
+ */
+pub fn transitive_closure(wv: &Weave, scope: Scope) -> BTreeMap<EntityId, Vec<EntityId>> {
+    let view = View::new(wv, scope);
+    view.nodes.iter().map(|&n| (n, view.reachable(n))).collect()
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use crate::core::{EntityId, Weave};
+use crate::traverse::{down, up};
+
+/*
+    Walks nested hoists, where an environment hoists entities that hoist
+    entities of their own (see `shape::hoist`). Every walk visits an
+    entity at most once, so hoisting something into itself or into one of
+    its own descendants can't send it round in circles.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoistTree {
+    pub entity: EntityId,
+    pub children: Vec<HoistTree>,
+}
+
+impl HoistTree {
+    /*
+        The entities in the tree, the root first and then depth first
+     */
+    pub fn entities(&self) -> Vec<EntityId> {
+        let mut ret = vec![ self.entity ];
+        for child in &self.children {
+            ret.extend(child.entities());
+        }
+        ret
+    }
+
+    pub fn depth(&self) -> usize {
+        self.children.iter().map(|c| c.depth() + 1).max().unwrap_or(0)
+    }
+}
+
+fn walk(wv: &Weave, from: EntityId, max_depth: Option<usize>, step: fn(&Weave, EntityId) -> Vec<EntityId>) -> Vec<EntityId> {
+    let mut seen = HashSet::from([ from ]);
+    let mut ret = vec![];
+    let mut level = vec![ from ];
+    let mut depth = 0;
+    while !level.is_empty() && max_depth.is_none_or(|m| depth < m) {
+        let mut next = level.iter()
+            .flat_map(|e| step(wv, *e))
+            .filter(|e| seen.insert(*e))
+            .collect::<Vec<_>>();
+        next.sort_unstable();
+        ret.extend(&next);
+        level = next;
+        depth += 1;
+    }
+
+    ret
+}
+
+/*
+    Everything hoisted into the environment, directly or through any
+    number of levels up to `max_depth` (`Some(1)` is the same as `down`).
+    Nearer levels come first, each in id order; the environment itself is
+    left out even if it is hoisted into itself.
+ */
+pub fn descendants(wv: &Weave, env: EntityId, max_depth: Option<usize>) -> Vec<EntityId> {
+    walk(wv, env, max_depth, down)
+}
+
+/*
+    Every environment the entity is hoisted into, directly or not, the
+    nearest first
+ */
+pub fn ancestors(wv: &Weave, entity: EntityId) -> Vec<EntityId> {
+    walk(wv, entity, None, up)
+}
+
+/*
+    The hoists below the environment as a tree, down to `max_depth` levels
+    (`Some(1)` gives just the environment's own children). The tree is
+    expanded rather than shared: an entity hoisted into several
+    environments shows up, with everything below it, under each of them,
+    so hierarchies that branch and join again grow quickly with depth and
+    are best walked with a cap or with `descendants`. An entity hoisted
+    into something it contains itself only shows up the first time.
+ */
+pub fn hoist_tree(wv: &Weave, env: EntityId, max_depth: Option<usize>) -> HoistTree {
+    fn build(wv: &Weave, entity: EntityId, max_depth: Option<usize>, path: &mut Vec<EntityId>) -> HoistTree {
+        if max_depth.is_some_and(|m| path.len() >= m) {
+            return HoistTree { entity, children: vec![] };
+        }
+
+        path.push(entity);
+        let children = down(wv, entity).into_iter()
+            .filter(|child| !path.contains(child))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|child| build(wv, child, max_depth, path))
+            .collect();
+        path.pop();
+
+        HoistTree { entity, children }
+    }
+
+    build(wv, env, max_depth, &mut vec![])
+}
@@ -1,44 +1,128 @@
-use std::collections::HashMap;
-use crate::core::{DatatypeId, EntityId, Weave};
+use std::collections::{HashMap, HashSet};
+use crate::core::{DataValue, DatatypeId, EntityId, Weave, WeaveError};
 use crate::shape::hoist;
 use crate::traverse::{down, next_n, tethers, virtuals};
 
-fn serialize_entity(wv: &Weave, id: EntityId, memory: &mut Vec<u8>) {
-    memory.extend((id as u64).to_ne_bytes());
-    memory.extend((wv.src(id) as u64).to_ne_bytes());
-    memory.extend((wv.tgt(id) as u64).to_ne_bytes());
+/*
+    Serialized weaves start with a magic word and the format version.
+    Files written before the header existed carry datatype ids produced by
+    std's `DefaultHasher`, which isn't stable between Rust releases, so the
+    ids in those files are ignored and datatypes are looked up by name.
+ */
+const MAGIC: u64 = u64::from_ne_bytes(*b"WEAVE\0\0\0");
+pub const LEGACY_FORMAT_VERSION: u64 = 1;
+pub const FORMAT_VERSION: u64 = 2;
+
+struct Record {
+    id: EntityId,
+    src: EntityId,
+    tgt: EntityId,
+    components: Vec<(String, DatatypeId, Vec<u8>)>,
+}
+
+fn corrupt(reason: impl Into<String>) -> WeaveError {
+    WeaveError::CorruptData(reason.into())
+}
+
+fn get_slice<'m>(memory: &'m [u8], index: &mut usize, len: usize) -> Result<&'m [u8], WeaveError> {
+    let bytes = index.checked_add(len)
+        .and_then(|end| memory.get(*index..end))
+        .ok_or_else(|| corrupt(format!("ends in the middle of a value at byte {}", index)))?;
+    *index += len;
+    Ok(bytes)
+}
+
+fn get_u64(memory: &[u8], index: &mut usize) -> Result<u64, WeaveError> {
+    let bytes: &[u8; 8] = get_slice(memory, index, 8)?.try_into().unwrap();
+    Ok(u64::from_ne_bytes(*bytes))
+}
+
+fn get_bytes<'m>(memory: &'m [u8], index: &mut usize) -> Result<&'m [u8], WeaveError> {
+    let len = get_u64(memory, index)? as usize;
+    get_slice(memory, index, len)
+}
+
+/*
+    The format version, with the index moved past the header. Versions
+    this build doesn't know are refused rather than misread.
+ */
+fn read_header(memory: &[u8], index: &mut usize) -> Result<u64, WeaveError> {
+    if memory.len() < 16 || get_u64(memory, &mut 0)? != MAGIC {
+        return Ok(LEGACY_FORMAT_VERSION);
+    }
+
+    *index = 8;
+    match get_u64(memory, index)? {
+        version @ LEGACY_FORMAT_VERSION..=FORMAT_VERSION => Ok(version),
+        version => Err(WeaveError::UnsupportedFormat(version)),
+    }
+}
 
-    let archetype = wv.get_archetype(id);
-    memory.extend((archetype.len() as u64).to_ne_bytes());
+fn write_header(memory: &mut Vec<u8>) {
+    memory.extend(MAGIC.to_ne_bytes());
+    memory.extend(FORMAT_VERSION.to_ne_bytes());
+}
+
+fn read_record(memory: &[u8], index: &mut usize) -> Result<Record, WeaveError> {
+    let id = get_u64(memory, index)? as EntityId;
+    let src = get_u64(memory, index)? as EntityId;
+    let tgt = get_u64(memory, index)? as EntityId;
+
+    let archetype_len = get_u64(memory, index)? as usize;
+    let mut components = vec![];
+    for _ in 0..archetype_len {
+        let name = std::str::from_utf8(get_bytes(memory, index)?)
+            .map_err(|_| corrupt(format!("datatype name of entity {} isn't UTF-8", id)))?
+            .to_string();
+        let datatype_id = get_u64(memory, index)? as DatatypeId;
+        let val = get_bytes(memory, index)?.to_vec();
+        components.push((name, datatype_id, val));
+    }
+
+    Ok(Record { id, src, tgt, components })
+}
+
+fn write_record(record: &Record, memory: &mut Vec<u8>) {
+    memory.extend((record.id as u64).to_ne_bytes());
+    memory.extend((record.src as u64).to_ne_bytes());
+    memory.extend((record.tgt as u64).to_ne_bytes());
+    memory.extend((record.components.len() as u64).to_ne_bytes());
 
-    for datatype in &archetype {
+    for (name, datatype, val) in &record.components {
+        let name_as_bytes = name.as_bytes();
+        memory.extend((name_as_bytes.len() as u64).to_ne_bytes());
+        memory.extend(name_as_bytes);
+        memory.extend(datatype.to_ne_bytes());
+        memory.extend((val.len() as u64).to_ne_bytes());
+        memory.extend(val);
+    }
+}
+
+fn serialize_entity(wv: &Weave, id: EntityId, memory: &mut Vec<u8>) {
+    let mut components = vec![];
+    for datatype in &wv.get_archetype(id) {
         let type_name = wv.type_names.get(datatype).unwrap();
-        if let Some(values) = wv.data.get(datatype) {
-            if let Some(val) = values.get(&id) {
-                let name_as_bytes = type_name.as_bytes();
-                memory.extend((name_as_bytes.len() as u64).to_ne_bytes());
-                memory.extend(name_as_bytes);
-                memory.extend(datatype.to_ne_bytes());
-                memory.extend((val.len() as u64).to_ne_bytes());
-                memory.extend(val);
-            }
+        if let Ok(component) = wv.try_get_component(id, type_name) {
+            let val = serde_json::to_string(&component.to_values()).expect("Fields can't stringify");
+            components.push((type_name.clone(), *datatype, val.into_bytes()));
         }
     }
+
+    write_record(&Record { id, src: wv.src(id), tgt: wv.tgt(id), components }, memory);
 }
 
 pub fn serialize(wv: &Weave, hoisted_env: EntityId) -> Vec<u8> {
-    let ignore_hoist_marks = next_n(wv, &next_n(wv, &tethers(wv, &[hoisted_env])));
-    let env = down(wv, hoisted_env);
-
-    let mut next_up = env.iter()
-        .filter(|&n| ignore_hoist_marks.contains(n))
-        .cloned().collect::<Vec<_>>();
+    let hoist_marks = next_n(wv, &tethers(wv, &[hoisted_env]));
+    let mut next_up = down(wv, hoisted_env);
+    let mut visited = HashSet::new();
     let mut memory = vec![];
+    write_header(&mut memory);
 
     while let Some(n) = next_up.pop() {
+        if !visited.insert(n) { continue; }
         serialize_entity(wv, n, &mut memory);
-        for id in virtuals(wv, &env) {
-            if ignore_hoist_marks.contains(&id) { continue; }
+        for id in virtuals(wv, &[n]) {
+            if hoist_marks.contains(&id) || visited.contains(&id) { continue; }
             next_up.push(id);
         }
     }
@@ -46,16 +130,61 @@ pub fn serialize(wv: &Weave, hoisted_env: EntityId) -> Vec<u8> {
     memory
 }
 
-fn deserialize_entity(wv: &mut Weave, memory: &[u8], index: &mut usize, mapping: &mut HashMap<EntityId, EntityId>) {
-    fn get_u64(memory: &[u8], index: &mut usize) -> u64 {
-        let bytes: &[u8; 8] = memory[*index..(*index + 8)].try_into().unwrap();
-        *index += 8;
-        u64::from_ne_bytes(*bytes)
+/*
+    Rewrites a serialized weave in the current format, replacing datatype
+    ids from older formats with the stable ones
+ */
+pub fn try_upgrade(serialized: &[u8]) -> Result<Vec<u8>, WeaveError> {
+    let mut i = 0;
+    let version = read_header(serialized, &mut i)?;
+    if version == FORMAT_VERSION {
+        return Ok(serialized.to_vec());
+    }
+
+    let mut memory = vec![];
+    write_header(&mut memory);
+    while i < serialized.len() {
+        let mut record = read_record(serialized, &mut i)?;
+        for (name, datatype, _) in &mut record.components {
+            *datatype = Weave::get_type_id(name);
+        }
+        write_record(&record, &mut memory);
+    }
+
+    Ok(memory)
+}
+
+pub fn upgrade(serialized: &[u8]) -> Vec<u8> {
+    try_upgrade(serialized).unwrap()
+}
+
+type PendingComponent = (EntityId, String, Vec<DataValue>);
+type RecordComponents = Vec<(String, Vec<DataValue>)>;
+
+/*
+    Reads the next entity along with its components' values, checking
+    everything that can be checked before the weave is touched
+ */
+fn read_entity(memory: &[u8], index: &mut usize, version: u64) -> Result<(Record, RecordComponents), WeaveError> {
+    let mut record = read_record(memory, index)?;
+    let mut components = vec![];
+    for (name, datatype_id, val) in std::mem::take(&mut record.components) {
+        if version >= FORMAT_VERSION && Weave::get_type_id(&name) != datatype_id {
+            return Err(corrupt(format!("datatype id of '{}' doesn't match its name", name)));
+        }
+
+        let values = std::str::from_utf8(&val).ok()
+            .and_then(|val| serde_json::from_str::<Vec<DataValue>>(val).ok())
+            .ok_or_else(|| corrupt(format!("fields of '{}' on entity {} don't parse", name, record.id)))?;
+        components.push((name, values));
     }
 
-    let id = get_u64(memory, index) as EntityId;
-    let src = get_u64(memory, index) as EntityId;
-    let tgt = get_u64(memory, index) as EntityId;
+    Ok((record, components))
+}
+
+fn deserialize_entity(wv: &mut Weave, record: Record, components: RecordComponents,
+                      mapping: &mut HashMap<EntityId, EntityId>, pending: &mut Vec<PendingComponent>) {
+    let Record { id, src, tgt, .. } = record;
 
     if !mapping.contains_key(&id) {
         mapping.insert(id, wv.new_knot());
@@ -72,37 +201,73 @@ fn deserialize_entity(wv: &mut Weave, memory: &[u8], index: &mut usize, mapping:
     let eid = *mapping.get(&id).unwrap();
     wv.change_ends(eid, *mapping.get(&src).unwrap(), *mapping.get(&tgt).unwrap());
 
-    let archetype_len = get_u64(memory, index) as usize;
-    for _ in 0..archetype_len {
-        let name_len = get_u64(memory, index) as usize;
-        let name_mem = memory[*index..(*index + name_len)].to_vec();
-        let name = std::str::from_utf8(&name_mem).unwrap();
-        *index += name_len;
-        let datatype_id = get_u64(memory, index) as DatatypeId;
-        let val_len = get_u64(memory, index) as usize;
-        let val_mem = memory[*index..(*index + val_len)].to_vec();
-        let val = std::str::from_utf8(&val_mem).unwrap();
-        *index += val_len;
-
-        assert_eq!(wv.get_datatype_id(name), datatype_id);
-        wv.add_component_raw(eid, name, val.as_bytes());
+    for (name, values) in components {
+        pending.push((eid, name, values));
     }
 }
 
-pub fn deserialize(wv: &mut Weave, serialized: &[u8]) -> EntityId {
-    let mut mapping = HashMap::new();
+/*
+    Entity handles stored in component fields belong to the weave that was
+    serialized; they get translated to the loaded entities, and anything
+    outside of the serialized environment becomes NIL
+ */
+fn remap_entity_values(values: &mut [DataValue], mapping: &HashMap<EntityId, EntityId>) {
+    for value in values {
+        match value {
+            DataValue::Entity(e) => *e = mapping.get(e).copied().unwrap_or(Weave::NIL),
+            DataValue::List(_, items) => remap_entity_values(items, mapping),
+            DataValue::Optional(_, Some(item)) => remap_entity_values(std::slice::from_mut(item.as_mut()), mapping),
+            _ => {}
+        }
+    }
+}
 
+/*
+    Loads a serialized weave hoisted under a new knot, which is returned.
+    The whole input is read and checked first, so a corrupt or unsupported
+    one leaves the weave as it was; if one of its components is refused
+    (say by strict datatypes) the entities loaded so far are deleted again.
+ */
+pub fn try_deserialize(wv: &mut Weave, serialized: &[u8]) -> Result<EntityId, WeaveError> {
     let mut i = 0;
-    let len = serialized.len();
+    let version = read_header(serialized, &mut i)?;
+    let mut entities = vec![];
+    while i < serialized.len() {
+        entities.push(read_entity(serialized, &mut i, version)?);
+    }
 
+    let mut mapping = HashMap::new();
     let parent = wv.new_knot();
 
-    while i < len {
-        deserialize_entity(wv, &serialized, &mut i, &mut mapping);
+    let mut pending = vec![];
+    for (record, components) in entities {
+        deserialize_entity(wv, record, components, &mut mapping, &mut pending);
+    }
+
+    let mut added = vec![];
+    for (eid, name, mut values) in pending {
+        remap_entity_values(&mut values, &mapping);
+        match wv.try_add_component(eid, &name, &values) {
+            Ok(()) => added.push((eid, name)),
+            Err(WeaveError::ComponentExists { .. }) => {}
+            Err(e) => {
+                for (eid, name) in added {
+                    wv.remove_component(eid, &name);
+                }
+                for eid in mapping.into_values().chain([ parent ]) {
+                    wv.delete_cascade(eid);
+                }
+                return Err(e);
+            }
+        }
     }
 
     hoist(wv, parent, &mapping.values().cloned()
         .filter(|&e| wv.is_knot(e) || wv.is_arrow(e)).collect::<Vec<_>>());
 
-    parent
-}
\ No newline at end of file
+    Ok(parent)
+}
+
+pub fn deserialize(wv: &mut Weave, serialized: &[u8]) -> EntityId {
+    try_deserialize(wv, serialized).unwrap()
+}
@@ -1,11 +1,20 @@
 #![allow(dead_code)]
 
+// lets `#[derive(Component)]` refer to `::wv` inside this crate too
+extern crate self as wv;
+
 pub mod core;
+pub mod component;
+pub mod storage;
 pub mod ffi;
 pub mod shape;
 pub mod tests;
 pub mod traverse;
+pub mod graph;
+pub mod hierarchy;
+pub mod path;
 pub mod search;
 pub mod io;
 pub mod replace;
 pub mod ds;
+pub mod query;
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::core::{EntityId, Weave};
+use crate::hierarchy::{ancestors, descendants};
+use crate::storage::DataRef;
+use crate::traverse::{arrows, arrows_in, arrows_out, deps, down_n, external_deps, marks, next_n, prev_n, primary, tethers, to_src, to_tgt, up_n, virtuals};
+
+/*
+    A small language for chains of moves, so that
+
+        to_tgt(wv, &to_tgt(wv, &arrows_out(wv, &tethers(wv, &[ x ]))))
+
+    filtered down to doors can be written as
+
+        tethers.arrows_out.tgt.tgt[With=Door]
+
+    Moves are separated by dots and named after the functions in
+    `traverse` (`src` and `tgt` stand for `to_src` and `to_tgt`, and
+    `descendants` and `ancestors` walk whole hoist hierarchies). Each move
+    can be followed by filters on the entities it reaches:
+
+        [Name]              has a `Name` component
+        [!Name]             doesn't have one
+        [Name=value]        has one whose first field is `value`
+        [Name.field=value]  has one whose `field` is `value`
+        [Name!=value]       has one whose first field isn't `value`
+
+    Values may be quoted ("a value") to include spaces, brackets or
+    quotes (escaped as \"). After every step the entities are sorted by id
+    and deduplicated.
+ */
+type Move = fn(&Weave, &[EntityId]) -> Vec<EntityId>;
+
+const MOVES: &[(&str, Move)] = &[
+    ("deps", deps),
+    ("external_deps", external_deps),
+    ("primary", primary),
+    ("virtuals", virtuals),
+    ("arrows", arrows),
+    ("arrows_in", arrows_in),
+    ("arrows_out", arrows_out),
+    ("marks", marks),
+    ("tethers", tethers),
+    ("src", to_src),
+    ("to_src", to_src),
+    ("tgt", to_tgt),
+    ("to_tgt", to_tgt),
+    ("prev", prev_n),
+    ("next", next_n),
+    ("down", down_n),
+    ("up", up_n),
+    ("descendants", |wv, it| it.iter().flat_map(|e| descendants(wv, *e, None)).collect()),
+    ("ancestors", |wv, it| it.iter().flat_map(|e| ancestors(wv, *e)).collect()),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathError {
+    UnknownMove { at: usize, name: String },
+    Expected { at: usize, expected: &'static str },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::UnknownMove { at, name } => write!(f, "unknown move '{}' at {}", name, at),
+            PathError::Expected { at, expected } => write!(f, "expected {} at {}", expected, at),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Has(String),
+    Lacks(String),
+    Equals { component: String, field: Option<String>, value: String, negated: bool },
+}
+
+impl Filter {
+    fn matches(&self, wv: &Weave, entity: EntityId) -> bool {
+        match self {
+            Filter::Has(name) => wv.has_component(entity, name),
+            Filter::Lacks(name) => !wv.has_component(entity, name),
+            Filter::Equals { component, field, value, negated } => {
+                let found = match field {
+                    Some(field) => wv.try_get_component_field(entity, component, field).ok(),
+                    None => wv.try_get_component(entity, component).ok().and_then(|c| c.get(0)),
+                };
+
+                found.is_some_and(|found| matches_literal(&found, value) != *negated)
+            }
+        }
+    }
+}
+
+fn matches_literal(value: &DataRef, literal: &str) -> bool {
+    match value {
+        DataRef::Entity(e) => literal.parse() == Ok(*e),
+        DataRef::Int(i) => literal.parse() == Ok(*i),
+        DataRef::Float(f) => literal.parse() == Ok(*f),
+        DataRef::Bool(b) => literal.parse() == Ok(*b),
+        DataRef::String(s) => *s == literal,
+        DataRef::Enum(_, variant) => *variant == literal,
+        DataRef::Optional(item) => item.get(0).is_some_and(|i| matches_literal(&i, literal)),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    name: &'static str,
+    apply: Move,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Path {
+    steps: Vec<Step>,
+}
+
+struct Parser<'s> {
+    source: &'s str,
+    at: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.at..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.at += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char, expected: &'static str) -> Result<(), PathError> {
+        if self.eat(c) { Ok(()) } else { Err(PathError::Expected { at: self.at, expected }) }
+    }
+
+    fn ident(&mut self, expected: &'static str) -> Result<&'s str, PathError> {
+        self.skip_whitespace();
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.at += self.peek().unwrap().len_utf8();
+        }
+
+        if self.at == start {
+            return Err(PathError::Expected { at: start, expected });
+        }
+        Ok(&self.source[start..self.at])
+    }
+
+    fn literal(&mut self) -> Result<String, PathError> {
+        self.skip_whitespace();
+        if !self.eat('"') {
+            let start = self.at;
+            while self.peek().is_some_and(|c| c != ']') {
+                self.at += self.peek().unwrap().len_utf8();
+            }
+            return Ok(self.source[start..self.at].trim_end().to_string());
+        }
+
+        let mut ret = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(PathError::Expected { at: self.at, expected: "closing quote" }),
+                Some('"') => {
+                    self.at += 1;
+                    return Ok(ret);
+                }
+                Some('\\') if self.source[self.at + 1..].starts_with('"') => {
+                    self.at += 2;
+                    ret.push('"');
+                }
+                Some(c) => {
+                    self.at += c.len_utf8();
+                    ret.push(c);
+                }
+            }
+        }
+    }
+
+    fn filter(&mut self) -> Result<Filter, PathError> {
+        if self.eat('!') {
+            let name = self.ident("component name")?;
+            return Ok(Filter::Lacks(name.to_string()));
+        }
+
+        let component = self.ident("component name")?.to_string();
+        let field = if self.eat('.') { Some(self.ident("field name")?.to_string()) } else { None };
+        let negated = self.eat('!');
+        if !self.eat('=') {
+            if negated || field.is_some() {
+                return Err(PathError::Expected { at: self.at, expected: "'='" });
+            }
+            return Ok(Filter::Has(component));
+        }
+
+        let value = self.literal()?;
+        Ok(Filter::Equals { component, field, value, negated })
+    }
+
+    fn step(&mut self) -> Result<Step, PathError> {
+        self.skip_whitespace();
+        let at = self.at;
+        let name = self.ident("move")?;
+        let &(name, apply) = MOVES.iter().find(|(n, _)| *n == name)
+            .ok_or_else(|| PathError::UnknownMove { at, name: name.to_string() })?;
+
+        let mut filters = vec![];
+        while self.eat('[') {
+            filters.push(self.filter()?);
+            self.expect(']', "']'")?;
+        }
+
+        Ok(Step { name, apply, filters })
+    }
+}
+
+impl FromStr for Path {
+    type Err = PathError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { source, at: 0 };
+        let mut steps = vec![ parser.step()? ];
+        while parser.eat('.') {
+            steps.push(parser.step()?);
+        }
+
+        parser.skip_whitespace();
+        if parser.at < source.len() {
+            return Err(PathError::Expected { at: parser.at, expected: "'.' or end of path" });
+        }
+
+        Ok(Path { steps })
+    }
+}
+
+impl Path {
+    pub fn parse(source: &str) -> Result<Path, PathError> {
+        source.parse()
+    }
+
+    pub fn eval(&self, wv: &Weave, from: &[EntityId]) -> Vec<EntityId> {
+        let mut current = from.to_vec();
+        for step in &self.steps {
+            current = (step.apply)(wv, &current);
+            current.retain(|&e| step.filters.iter().all(|f| f.matches(wv, e)));
+            current.sort_unstable();
+            current.dedup();
+        }
+
+        current
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", step.name)?;
+            for filter in &step.filters {
+                match filter {
+                    Filter::Has(name) => write!(f, "[{}]", name)?,
+                    Filter::Lacks(name) => write!(f, "[!{}]", name)?,
+                    Filter::Equals { component, field, value, negated } => {
+                        write!(f, "[{}", component)?;
+                        if let Some(field) = field {
+                            write!(f, ".{}", field)?;
+                        }
+                        write!(f, "{}=\"{}\"]", if *negated { "!" } else { "" }, value.replace('"', "\\\""))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+    Parses the path and runs it from the given entities
+ */
+pub fn query(wv: &Weave, from: &[EntityId], path: &str) -> Result<Vec<EntityId>, PathError> {
+    Ok(Path::parse(path)?.eval(wv, from))
+}
@@ -0,0 +1,123 @@
+use crate::core::{EntityId, MotifKind, Weave};
+use crate::storage::{ComponentRef, ComponentStore};
+
+/*
+    ECS-style selection of entities by the components they carry:
+
+        Query::new()
+            .with("Position").with("Velocity")
+            .without("Frozen")
+            .optional("Name")
+            .kind(MotifKind::Knot)
+            .iter(&wv)
+
+    yields every knot having both `Position` and `Velocity` and no `Frozen`,
+    together with its `Position`, `Velocity` and (maybe) `Name` components.
+    Entities come out in id order.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    with: Vec<String>,
+    without: Vec<String>,
+    optional: Vec<String>,
+    kinds: Vec<MotifKind>,
+}
+
+pub struct QueryItem<'w> {
+    pub entity: EntityId,
+    pub with: Vec<ComponentRef<'w>>,
+    pub optional: Vec<Option<ComponentRef<'w>>>,
+}
+
+pub struct QueryIter<'w> {
+    wv: &'w Weave,
+    candidates: std::vec::IntoIter<EntityId>,
+    with: Vec<&'w ComponentStore>,
+    without: Vec<&'w ComponentStore>,
+    optional: Vec<Option<&'w ComponentStore>>,
+    kinds: Vec<MotifKind>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: &str) -> Self {
+        self.with.push(name.to_string());
+        self
+    }
+
+    pub fn without(mut self, name: &str) -> Self {
+        self.without.push(name.to_string());
+        self
+    }
+
+    pub fn optional(mut self, name: &str) -> Self {
+        self.optional.push(name.to_string());
+        self
+    }
+
+    /*
+        Restricts results to the given motif kind; calling it several times
+        accepts any of the given kinds
+     */
+    pub fn kind(mut self, kind: MotifKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    pub fn iter<'w>(&self, wv: &'w Weave) -> QueryIter<'w> {
+        let store = |name: &String| wv.data.get(&wv.get_datatype_id(name));
+        let with = self.with.iter().map(store).collect::<Option<Vec<_>>>();
+
+        let candidates = match &with {
+            Some(_) => {
+                let names = self.with.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+                wv.entities_with(&names)
+            }
+            None => vec![],
+        };
+
+        QueryIter {
+            wv,
+            candidates: candidates.into_iter(),
+            with: with.unwrap_or_default(),
+            without: self.without.iter().filter_map(store).collect(),
+            optional: self.optional.iter().map(store).collect(),
+            kinds: self.kinds.clone(),
+        }
+    }
+
+    pub fn entities(&self, wv: &Weave) -> Vec<EntityId> {
+        self.iter(wv).map(|item| item.entity).collect()
+    }
+}
+
+impl<'w> Iterator for QueryIter<'w> {
+    type Item = QueryItem<'w>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let component = |store: &'w ComponentStore, entity: EntityId| {
+            store.rows.get(&entity).map(|row| ComponentRef { store, row: *row, strings: &self.wv.strings })
+        };
+
+        for entity in self.candidates.by_ref() {
+            if self.without.iter().any(|s| s.contains(entity)) {
+                continue;
+            }
+
+            if !self.kinds.is_empty() && !self.kinds.contains(&self.wv.motif_kind(entity)) {
+                continue;
+            }
+
+            return Some(QueryItem {
+                entity,
+                with: self.with.iter().filter_map(|s| component(s, entity)).collect(),
+                optional: self.optional.iter().map(|s| s.and_then(|s| component(s, entity))).collect(),
+            });
+        }
+
+        None
+    }
+}
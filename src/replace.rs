@@ -1,9 +1,10 @@
 use std::cmp::Ordering;
 use std::collections::{HashMap};
 use multimap::MultiMap;
-use crate::core::{DataValue, EntityId, Weave};
-use crate::search::{find_one, prepare_search_space, SearchSpace};
+use crate::core::{EntityId, Weave};
+use crate::search::{find_one, prepare_search_space, MatchOptions, SearchSpace};
 use crate::shape::{get_annotation, hoist};
+use crate::storage::DataRef;
 use crate::traverse::down;
 
 #[derive(Debug)]
@@ -60,7 +61,7 @@ pub(crate) fn generate_incomplete_products(wv: &Weave, search_space: &SearchSpac
 
     let mut ret = Vec::new();
     let mut seed: HashMap<EntityId, Option<EntityId>> = seed.iter().map(|(k, v)| (*k, Some(*v))).collect();
-    let mut seeded_search_space = SearchSpace { entities: search_space.entities.clone(), candidates: search_space.candidates.clone() };
+    let mut seeded_search_space = search_space.clone();
     seeded_search_space.entities.sort_by(|a, b| {
         if seed.contains_key(a) && !seed.contains_key(b) {
             return Ordering::Less;
@@ -123,13 +124,13 @@ pub(crate) fn get_match_mapping(wv: &Weave, hoisted_pattern: EntityId, hoisted_g
     for motif in &goal {
         let ann = get_annotation(wv, *motif, "Identity");
         if let Some(ann) = ann {
-            if let DataValue::Entity(eid) = wv.get_component(ann, "Identity").first().unwrap() {
-                annotated_identities.insert(*eid as EntityId, *motif);
+            if let DataRef::Entity(eid) = wv.get_component(ann, "Identity").get(0).unwrap() {
+                annotated_identities.insert(eid, *motif);
             }
         }
     }
 
-    if let Some(search_space) = prepare_search_space(wv, hoisted_pattern, hoisted_goal, &annotated_identities) {
+    if let Some(search_space) = prepare_search_space(wv, hoisted_pattern, hoisted_goal, &annotated_identities, MatchOptions::default()) {
         // println!("{:?}", search_space);
         // println!("{:?}", annotated_identities);
         let prod = generate_incomplete_products(wv, &search_space, annotated_identities);
@@ -165,6 +166,8 @@ pub fn replace(wv: &mut Weave,
         if let Some(matching_target) = pattern_to_target {
             // println!("2. PATTERN <-> TARGET: {:?}", matching_target);
             let mut gt: MultiMap<Option<EntityId>, Option<EntityId>> = MultiMap::new();
+            let mut matching_goal = matching_goal.into_iter().collect::<Vec<_>>();
+            matching_goal.sort_unstable();
             for (p, g) in &matching_goal {
                 gt.insert(*g, matching_target.get(p).cloned());
             }
@@ -192,8 +195,12 @@ pub fn replace(wv: &mut Weave,
                 }
             }
 
+            // bindings are spawned in goal order, keeping the result's ids stable
+            let mut bindings = gt.into_iter().collect::<Vec<_>>();
+            bindings.sort_unstable_by_key(|(k, _)| *k);
+
             let result = wv.new_knot();
-            for (k, v) in gt {
+            for (k, v) in bindings {
                 let binding = wv.new_tether(result);
                 let left = wv.new_tether(binding);
                 let right = wv.new_tether(binding);
@@ -1,9 +1,13 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 use multimap::MultiMap;
+use regex::Regex;
 use crate::core::{DataField, DataValue, EntityId, Weave};
 use crate::shape::{annotate};
-use crate::traverse::{arrows_in, arrows_out, down, marks};
+use crate::storage::DataRef;
+use crate::traverse::{arrows_in, arrows_out, down, down_n, marks};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Diff {
@@ -14,13 +18,385 @@ pub enum Diff {
     ChangeData(EntityId, String, Vec<DataField>),
 }
 
-#[derive(Debug)]
+/*
+    How pattern entities may land on target entities. Injective matching
+    sends different pattern entities to different target entities, while
+    homomorphic matching lets several share one. Induced matching also
+    rejects target arrows between matched entities that the pattern has
+    no arrow for. Defaults to injective and non-induced.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchOptions {
+    pub injective: bool,
+    pub induced: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions { injective: true, induced: false }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct SearchSpace {
     pub(crate) entities: Vec<EntityId>,
     pub(crate) candidates: MultiMap<EntityId, EntityId>,
+    pub(crate) target: EntityId,
+    pub(crate) in_target: Vec<EntityId>,
+    pub(crate) forbidden: Vec<SearchSpace>,
+    pub(crate) options: MatchOptions,
+}
+
+/*
+    Why a stream of matches ended early (see `Matches`)
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Count,
+    Steps,
+    Timeout,
+    Cancelled,
+}
+
+/*
+    A pattern entity's candidates, with how far through them the search is
+ */
+struct Frame {
+    candidates: Vec<EntityId>,
+    at: usize,
+    fresh: bool,
+}
+
+/*
+    Subgraph matching in the style of VF2. Pattern entities are matched
+    one at a time, each next to ones already matched where possible, so
+    its candidates can be read off their images' arrows instead of tried
+    one by one; every assignment is checked against the matched ends
+    straight away, cutting off whole branches that can't work out. The
+    search keeps its own stack, so it can hand out a match and pick up
+    where it left off.
+ */
+struct Matcher<'a> {
+    wv: &'a Weave,
+    search_space: Cow<'a, SearchSpace>,
+    order: Vec<EntityId>,
+    candidates: HashMap<EntityId, HashSet<EntityId>>,
+    known: HashSet<EntityId>,
+    assigned: HashMap<EntityId, EntityId>,
+    used: HashSet<EntityId>,
+    frames: Vec<Frame>,
+    descend: bool,
+    steps: usize,
+    max_steps: Option<usize>,
+    deadline: Option<Instant>,
+    cancel: Option<Box<dyn FnMut() -> bool + 'a>>,
+    stopped: Option<StopReason>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(wv: &'a Weave, search_space: Cow<'a, SearchSpace>, seed: HashMap<EntityId, EntityId>) -> Self {
+        let candidates = search_space.entities.iter()
+            .map(|e| (*e, search_space.candidates.get_vec(e).into_iter().flatten().copied().collect::<HashSet<_>>()))
+            .collect::<HashMap<_, _>>();
+        let known = search_space.entities.iter().chain(seed.keys()).copied().collect();
+        let used = if search_space.options.injective { seed.values().copied().collect() } else { HashSet::new() };
+
+        let mut matcher = Matcher {
+            wv, search_space,
+            order: vec![],
+            candidates,
+            known,
+            assigned: seed,
+            used,
+            frames: vec![],
+            descend: false,
+            steps: 0,
+            max_steps: None,
+            deadline: None,
+            cancel: None,
+            stopped: None,
+        };
+        matcher.order = matcher.matching_order();
+        matcher.descend = matcher.assigned.keys().all(|e| matcher.is_consistent(*e));
+        matcher
+    }
+
+    fn is_neighbour(&self, e: EntityId, of: &HashSet<EntityId>) -> bool {
+        of.contains(&self.wv.src(e)) || of.contains(&self.wv.tgt(e))
+            || self.wv.dependents(e).any(|d| of.contains(&d))
+    }
+
+    /*
+        Fewest candidates first, then always an entity next to the ones
+        before it if there is one
+     */
+    fn matching_order(&self) -> Vec<EntityId> {
+        let mut left = self.search_space.entities.iter()
+            .filter(|e| !self.assigned.contains_key(e))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut placed = self.assigned.keys().copied().collect::<HashSet<_>>();
+        let mut order = vec![];
+
+        while !left.is_empty() {
+            let pick = |connected: bool| left.iter().enumerate()
+                .filter(|(_, e)| !connected || self.is_neighbour(**e, &placed))
+                .min_by_key(|(_, e)| (self.candidates[*e].len(), **e))
+                .map(|(i, _)| i);
+
+            let i = pick(true).or_else(|| pick(false)).unwrap();
+            let next = left.remove(i);
+            placed.insert(next);
+            order.push(next);
+        }
+
+        order
+    }
+
+    /*
+        Where the entity can go given what's matched so far: among the
+        arrows of its ends' images, or at the end of an arrow's image
+     */
+    fn candidates_for(&self, p: EntityId) -> Vec<EntityId> {
+        let (wv, (src, tgt)) = (self.wv, (self.wv.src(p), self.wv.tgt(p)));
+        let derived = if let (true, Some(&s)) = (src != p, self.assigned.get(&src)) {
+            wv.get_dependents_for_source(s).to_vec()
+        } else if let (true, Some(&t)) = (tgt != p, self.assigned.get(&tgt)) {
+            wv.get_dependents_for_target(t).to_vec()
+        } else {
+            wv.dependents(p)
+                .filter(|&d| d != p)
+                .find_map(|d| self.assigned.get(&d).map(|&md| if wv.src(d) == p { wv.src(md) } else { wv.tgt(md) }))
+                .map_or_else(|| self.search_space.candidates.get_vec(&p).cloned().unwrap_or_default(), |c| vec![ c ])
+        };
+
+        let candidates = &self.candidates[&p];
+        derived.into_iter()
+            .filter(|c| candidates.contains(c) && !self.used.contains(c))
+            .collect()
+    }
+
+    /*
+        Whether the entity's match agrees with the matches of its ends, and
+        with those of the entities it is an end of
+     */
+    fn is_consistent(&self, p: EntityId) -> bool {
+        let wv = self.wv;
+        std::iter::once(p).chain(wv.dependents(p)).all(|d| {
+            let Some(&md) = self.assigned.get(&d) else { return true };
+            let agrees = |end: EntityId, image: EntityId| !self.known.contains(&end)
+                || self.assigned.get(&end).is_none_or(|&m| m == image);
+            agrees(wv.src(d), wv.src(md)) && agrees(wv.tgt(d), wv.tgt(md))
+        })
+    }
+
+    /*
+        Counts a step, and returns whether the search may take it
+     */
+    fn within_budget(&mut self) -> bool {
+        self.steps += 1;
+        self.stopped = if self.max_steps.is_some_and(|m| self.steps > m) {
+            Some(StopReason::Steps)
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(StopReason::Timeout)
+        } else if self.cancel.as_mut().is_some_and(|cancel| cancel()) {
+            Some(StopReason::Cancelled)
+        } else {
+            None
+        };
+
+        self.stopped.is_none()
+    }
+
+    fn unassign(&mut self, depth: usize) {
+        let frame = &mut self.frames[depth];
+        if frame.at > 0 {
+            self.assigned.remove(&self.order[depth]);
+            if frame.fresh {
+                self.used.remove(&frame.candidates[frame.at - 1]);
+            }
+            frame.fresh = false;
+        }
+    }
+
+    fn next_match(&mut self) -> Option<HashMap<EntityId, EntityId>> {
+        while self.stopped.is_none() {
+            if self.descend {
+                self.descend = false;
+                let depth = self.frames.len();
+                if depth == self.order.len() {
+                    if accepts_complete(self.wv, &self.search_space, &self.assigned) {
+                        return Some(self.assigned.clone());
+                    }
+                } else {
+                    let candidates = self.candidates_for(self.order[depth]);
+                    self.frames.push(Frame { candidates, at: 0, fresh: false });
+                }
+                continue;
+            }
+
+            // move the deepest entity on to its next candidate, or give up
+            // on it and go back to the one before
+            let depth = self.frames.len().checked_sub(1)?;
+            self.unassign(depth);
+            let frame = &mut self.frames[depth];
+            let Some(&candidate) = frame.candidates.get(frame.at) else {
+                self.frames.pop();
+                continue;
+            };
+
+            // checked before moving on so a search stopped here can resume
+            if !self.within_budget() {
+                return None;
+            }
+            self.frames[depth].at += 1;
+
+            let p = self.order[depth];
+            self.assigned.insert(p, candidate);
+            self.frames[depth].fresh = self.search_space.options.injective && self.used.insert(candidate);
+            self.descend = self.is_consistent(p);
+        }
+
+        None
+    }
+}
+
+/*
+    Matches of a pattern in a target, found one at a time as they are
+    asked for rather than all up front. The search can be held to a number
+    of matches, a number of steps (candidates tried), a time limit or a
+    callback that says when to give up; once it stops, `stop_reason` tells
+    why, or is None if every match was handed out. Reaching `max_count`
+    stops it without looking any further, so whether there were more
+    matches is left to `has_more`.
+
+        for m in matches(wv, pattern, target).max_count(10).time_limit(Duration::from_secs(1)) {
+            ...
+        }
+ */
+pub struct Matches<'a> {
+    matcher: Option<Matcher<'a>>,
+    max_count: Option<usize>,
+    count: usize,
+    stopped: Option<StopReason>,
+    peeked: Option<HashMap<EntityId, EntityId>>,
+}
+
+impl<'a> Matches<'a> {
+    pub fn max_count(mut self, count: usize) -> Self {
+        self.max_count = Some(count);
+        self
+    }
+
+    pub fn max_steps(mut self, steps: usize) -> Self {
+        if let Some(m) = &mut self.matcher {
+            m.max_steps = Some(steps);
+        }
+        self
+    }
+
+    /*
+        Gives up once `limit` has passed since this call
+     */
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        if let Some(m) = &mut self.matcher {
+            m.deadline = Some(Instant::now() + limit);
+        }
+        self
+    }
+
+    /*
+        Gives up as soon as `cancel` returns true. It's called before every
+        step, so it should be cheap.
+     */
+    pub fn cancel_when(mut self, cancel: impl FnMut() -> bool + 'a) -> Self {
+        if let Some(m) = &mut self.matcher {
+            m.cancel = Some(Box::new(cancel));
+        }
+        self
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stopped.or(self.matcher.as_ref().and_then(|m| m.stopped))
+    }
+
+    pub fn steps(&self) -> usize {
+        self.matcher.as_ref().map_or(0, |m| m.steps)
+    }
+
+    /*
+        Whether there is another match, searching at most `max_steps`
+        further steps to find out, and past `max_count` too. None if that
+        wasn't enough to tell or the search had already been stopped; a
+        match found on the way is kept for `next`.
+     */
+    pub fn has_more(&mut self, max_steps: usize) -> Option<bool> {
+        if self.peeked.is_some() {
+            return Some(true);
+        }
+        let Some(m) = &mut self.matcher else { return Some(false) };
+        if m.stopped.is_some() {
+            return None;
+        }
+
+        let limit = m.max_steps;
+        m.max_steps = Some(limit.map_or(m.steps + max_steps, |l| l.min(m.steps + max_steps)));
+        self.peeked = m.next_match();
+        m.max_steps = limit;
+
+        if self.peeked.is_some() {
+            return Some(true);
+        }
+        match m.stopped {
+            None => Some(false),
+            // out of the steps given here rather than the search's own, so
+            // put the step back and let the search carry on later
+            Some(StopReason::Steps) if limit.is_none_or(|l| m.steps <= l) => {
+                m.stopped = None;
+                m.steps -= 1;
+                None
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+impl Iterator for Matches<'_> {
+    type Item = HashMap<EntityId, EntityId>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.max_count.is_some_and(|m| self.count >= m) {
+            self.stopped = self.stopped.or(Some(StopReason::Count));
+            return None;
+        }
+
+        let next = self.peeked.take().or_else(|| self.matcher.as_mut()?.next_match());
+        self.count += next.is_some() as usize;
+        next
+    }
+}
+
+pub fn matches(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId) -> Matches<'_> {
+    matches_with(wv, hoist_pattern, hoist_target, MatchOptions::default())
+}
+
+pub fn matches_with(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, options: MatchOptions) -> Matches<'_> {
+    let seed = HashMap::default();
+    let matcher = prepare_search_space(wv, hoist_pattern, hoist_target, &seed, options)
+        .map(|search_space| Matcher::new(wv, Cow::Owned(search_space), seed));
+    Matches { matcher, max_count: None, count: 0, stopped: None, peeked: None }
 }
 
 pub(crate) fn generate_single_product(wv: &Weave, search_space: &SearchSpace, seed: HashMap<EntityId, EntityId>) -> Option<HashMap<EntityId, EntityId>> {
+    Matcher::new(wv, Cow::Borrowed(search_space), seed).next_match()
+}
+
+/*
+    The search from before `Matcher`: tries every assignment of candidates
+    and only checks the arrows of complete ones. Kept as a reference for
+    tests and benchmarks.
+ */
+pub(crate) fn exhaustive_single_product(wv: &Weave, search_space: &SearchSpace, seed: HashMap<EntityId, EntityId>) -> Option<HashMap<EntityId, EntityId>> {
     fn rec_generate_product(wv: &Weave, index: usize,
                             search_space: &SearchSpace,
                             used: &mut Vec<EntityId>,
@@ -29,7 +405,7 @@ pub(crate) fn generate_single_product(wv: &Weave, search_space: &SearchSpace, se
         let next = search_space.entities[index];
 
         for candidate in search_space.candidates.get_vec(&next).unwrap() {
-            if used.contains(candidate) {
+            if search_space.options.injective && used.contains(candidate) {
                 continue;
             }
 
@@ -40,7 +416,7 @@ pub(crate) fn generate_single_product(wv: &Weave, search_space: &SearchSpace, se
                     return Some(res);
                 }
             } else {
-                if check_search_solution(wv, &collected) {
+                if accepts_solution(wv, search_space, collected) {
                     return Some(collected.clone());
                 }
             }
@@ -53,10 +429,13 @@ pub(crate) fn generate_single_product(wv: &Weave, search_space: &SearchSpace, se
     }
 
     let mut seed = seed;
+    if search_space.entities.is_empty() {
+        return accepts_solution(wv, search_space, &seed).then_some(seed);
+    }
     rec_generate_product(wv, 0, search_space, &mut vec![], &mut seed)
 }
 
-pub(crate) fn generate_products(wv: &Weave, search_space: &SearchSpace, seed: HashMap<EntityId, EntityId>) -> Vec<HashMap<EntityId, EntityId>> {
+pub(crate) fn exhaustive_products(wv: &Weave, search_space: &SearchSpace, seed: HashMap<EntityId, EntityId>) -> Vec<HashMap<EntityId, EntityId>> {
     fn rec_generate_products(wv: &Weave, index: usize,
                              search_space: &SearchSpace,
                              used: &mut Vec<EntityId>,
@@ -67,7 +446,7 @@ pub(crate) fn generate_products(wv: &Weave, search_space: &SearchSpace, seed: Ha
 
         if let Some(v) = search_space.candidates.get_vec(&next) {
             for candidate in v {
-                if used.contains(candidate) {
+                if search_space.options.injective && used.contains(candidate) {
                     continue;
                 }
 
@@ -76,7 +455,7 @@ pub(crate) fn generate_products(wv: &Weave, search_space: &SearchSpace, seed: Ha
                 if index < search_space.entities.len() - 1 {
                     rec_generate_products(wv, index + 1, search_space, used, collected, ret);
                 } else {
-                    if check_search_solution(wv, &collected) {
+                    if accepts_solution(wv, search_space, collected) {
                         ret.push(collected.clone());
                     }
                 }
@@ -89,6 +468,12 @@ pub(crate) fn generate_products(wv: &Weave, search_space: &SearchSpace, seed: Ha
 
     let mut ret = Vec::new();
     let mut seed = seed;
+    if search_space.entities.is_empty() {
+        if accepts_solution(wv, search_space, &seed) {
+            ret.push(seed);
+        }
+        return ret;
+    }
     rec_generate_products(wv, 0, search_space, &mut vec![], &mut seed, &mut ret);
 
     ret
@@ -115,55 +500,131 @@ pub(crate) fn check_search_solution(wv: &Weave, solution: &HashMap<EntityId, Ent
     true
 }
 
-pub(crate) fn prepare_search_space(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, seed: &HashMap<EntityId, EntityId>) -> Option<SearchSpace> {
-    fn get_component_name<'s>(wv: &Weave, e: EntityId) -> String {
-        if let DataValue::String(s) = wv.get_component(e, "With").first().unwrap() {
-            s.clone()
+pub(crate) fn accepts_solution(wv: &Weave, search_space: &SearchSpace, solution: &HashMap<EntityId, EntityId>) -> bool {
+    check_search_solution(wv, solution) && accepts_complete(wv, search_space, solution)
+}
+
+/*
+    The checks left once every entity is matched consistently
+ */
+pub(crate) fn accepts_complete(wv: &Weave, search_space: &SearchSpace, solution: &HashMap<EntityId, EntityId>) -> bool {
+    (!search_space.options.induced || is_induced(wv, search_space, solution))
+        && !is_forbidden(wv, search_space, solution)
+}
+
+/*
+    Whether every target arrow between matched entities is matched too
+ */
+pub(crate) fn is_induced(wv: &Weave, search_space: &SearchSpace, solution: &HashMap<EntityId, EntityId>) -> bool {
+    let matched = solution.values().copied().collect::<HashSet<_>>();
+    matched.iter().all(|&e| {
+        arrows_out(wv, &[ e ]).iter()
+            .filter(|a| search_space.in_target.binary_search(a).is_ok())
+            .all(|a| !matched.contains(&wv.tgt(*a)) || matched.contains(a))
+    })
+}
+
+/*
+    Whether one of the pattern's negative conditions extends the solution
+    into the target: the pattern entities the condition shares keep their
+    match, and its own entities go to target entities outside of it. The
+    conditions' search spaces are prepared along with the pattern's, so
+    each match only has to seed them.
+ */
+pub(crate) fn is_forbidden(wv: &Weave, search_space: &SearchSpace, solution: &HashMap<EntityId, EntityId>) -> bool {
+    search_space.forbidden.iter()
+        .any(|nac| generate_single_product(wv, nac, solution.clone()).is_some())
+}
+
+pub(crate) fn prepare_search_space(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId,
+                                   seed: &HashMap<EntityId, EntityId>, options: MatchOptions) -> Option<SearchSpace> {
+    fn get_component_name(wv: &Weave, e: EntityId, annotation: &str) -> String {
+        if let DataRef::String(s) = wv.get_component(e, annotation).get(0).unwrap() {
+            s.to_string()
         } else {
             panic!("Component name isn't a string!");
         }
     }
 
-    let mut seed_vals: HashMap<EntityId, EntityId> = HashMap::new();
-    for (k, v) in seed {
-        seed_vals.insert(*v, *k);
-    }
+    let nacs = marks(wv, &[ hoist_pattern ]).iter()
+        .filter_map(|&m| wv.try_get_component(m, "Forbid").ok().and_then(|c| c.get(0)))
+        .filter_map(|nac| if let DataRef::Entity(nac) = nac { Some(nac) } else { None })
+        .filter(|&nac| wv.is_valid(nac) && !down(wv, nac).is_empty())
+        .collect::<Vec<_>>();
 
+    // hoisting a pattern entity also hoists its arrows, including any a
+    // negative condition added to it; those reach outside the pattern and
+    // belong to the condition only
     let mut in_pattern = down(wv, hoist_pattern);
+    let in_nacs = down_n(wv, &nacs);
+    loop {
+        let before = in_pattern.clone();
+        in_pattern.retain(|e| in_nacs.binary_search(e).is_err()
+            || [ wv.src(*e), wv.tgt(*e) ].iter().all(|end| before.binary_search(end).is_ok()));
+        if in_pattern.len() == before.len() {
+            break;
+        }
+    }
     let in_target = down(wv, hoist_target);
 
     let mut degrees = MultiMap::new();
     let mut with_components = HashMap::new();
     let mut without_components = HashMap::new();
+    let mut where_predicates = HashMap::new();
     let mut candidates = MultiMap::new();
 
+    // only arrows that are part of the pattern count, not those of negative
+    // conditions sharing its entities
+    let in_pattern_degree = |arrows: Vec<EntityId>| arrows.iter()
+        .filter(|a| in_pattern.binary_search(a).is_ok())
+        .count();
+
     for entity in &in_pattern {
-        let in_degree = arrows_in(wv, &[ *entity ]).len();
-        let out_degree = arrows_out(wv, &[ *entity ]).len();
+        let in_degree = in_pattern_degree(arrows_in(wv, &[ *entity ]));
+        let out_degree = in_pattern_degree(arrows_out(wv, &[ *entity ]));
         let withs = marks(wv, &[ *entity ]).iter()
             .filter(|&m| wv.has_component(*m, "With"))
-            .map(|&m| get_component_name(wv, m))
+            .map(|&m| get_component_name(wv, m, "With"))
             .collect::<Vec<_>>();
         let withouts = marks(wv, &[ *entity ]).iter()
             .filter(|&m| wv.has_component(*m, "Without"))
-            .map(|&m| get_component_name(wv, m))
+            .map(|&m| get_component_name(wv, m, "Without"))
+            .collect::<Vec<_>>();
+        let wheres = marks(wv, &[ *entity ]).iter()
+            .filter(|&m| wv.has_component(*m, "Where"))
+            .map(|&m| Predicate::read(wv, m))
             .collect::<Vec<_>>();
         degrees.insert(*entity, (in_degree, out_degree));
         with_components.insert(*entity, withs);
         without_components.insert(*entity, withouts);
+        where_predicates.insert(*entity, wheres);
     }
 
+    // seeded entities keep their match, and with injective matching what
+    // they matched can't go to anything else
+    for (p, t) in seed {
+        if in_pattern.binary_search(p).is_ok() {
+            candidates.insert(*p, *t);
+        }
+    }
+    let seeded = seed.values().copied().collect::<HashSet<_>>();
+
     for entity in &in_target {
-        if seed_vals.contains_key(&entity) {
-            candidates.insert(seed_vals.get(&entity).unwrap().clone(), *entity);
+        if options.injective && seeded.contains(entity) {
             continue;
         }
 
-        let mut has_candidates = false;
+        // several pattern arrows can share a target arrow when matching
+        // isn't injective, so degrees only bound injective matches
         let in_degree = arrows_in(wv, &[ *entity ]).len();
         let out_degree = arrows_out(wv, &[ *entity ]).len();
         'candidates: for (&candidate, &(in_d, out_d)) in degrees.iter() {
-            if in_degree >= in_d && out_degree >= out_d {
+            // knots only match knots and arrows only arrows
+            if wv.is_knot(*entity) != wv.is_knot(candidate) || wv.is_arrow(*entity) != wv.is_arrow(candidate) {
+                continue;
+            }
+
+            if !options.injective || (in_degree >= in_d && out_degree >= out_d) {
                 let withs = with_components.get(&candidate).unwrap();
                 for with in withs {
                     if !wv.has_component(*entity, with) {
@@ -178,16 +639,22 @@ pub(crate) fn prepare_search_space(wv: &Weave, hoist_pattern: EntityId, hoist_ta
                     }
                 }
 
+                let wheres = where_predicates.get(&candidate).unwrap();
+                if !wheres.iter().all(|p| p.holds(wv, *entity)) {
+                    continue 'candidates;
+                }
+
                 if !seed.contains_key(&candidate) {
                     candidates.insert(candidate, *entity);
-                    has_candidates = true;
                 }
             }
         }
+    }
 
-        if !has_candidates {
-            return None;
-        }
+    // target entities fitting no pattern entity are simply left out, but a
+    // pattern entity nothing fits means there can't be a match
+    if in_pattern.iter().any(|e| candidates.get_vec(e).is_none()) {
+        return None;
     }
 
     if candidates.len() >= in_pattern.len() {
@@ -202,9 +669,17 @@ pub(crate) fn prepare_search_space(wv: &Weave, hoist_pattern: EntityId, hoist_ta
         });
     }
 
+    let forbidden = nacs.into_iter()
+        .filter_map(|nac| prepare_search_space(wv, nac, hoist_target, &HashMap::new(), options))
+        .collect();
+
     Some(SearchSpace {
         entities: in_pattern,
         candidates,
+        target: hoist_target,
+        in_target,
+        forbidden,
+        options,
     })
 }
 
@@ -216,20 +691,194 @@ pub fn require_no_component(wv: &mut Weave, entity: EntityId, name: &str) {
     annotate(wv, entity, "Without", &[ DataValue::String(name.to_string()) ]);
 }
 
+/*
+    Attaches a negative condition to a hoisted pattern: matches that the
+    entities hoisted into `nac` could extend are rejected. The condition
+    hoists whichever pattern entities it refers to alongside its own, so
+    with `y` requiring a `Locked` component,
+
+        let ay = wv.new_arrow(a, y);
+        hoist(wv, nac, &[ a, y, ay ]);
+
+    forbids `a` having an arrow to anything locked (other than what the
+    rest of the pattern matched). Hoisting `a` into the pattern after `ay`
+    exists hoists `ay` too, but it's left out of the pattern all the same,
+    as is anything else of the condition's that reaches outside the
+    pattern. Conditions with nothing hoisted into them are ignored.
+ */
+pub fn forbid(wv: &mut Weave, pattern: EntityId, nac: EntityId) {
+    annotate(wv, pattern, "Forbid", &[ DataValue::Entity(nac) ]);
+}
+
 pub fn find_all(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId) -> Vec<HashMap<EntityId, EntityId>> {
+    find_all_with(wv, hoist_pattern, hoist_target, MatchOptions::default())
+}
+
+pub fn find_all_with(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, options: MatchOptions) -> Vec<HashMap<EntityId, EntityId>> {
+    matches_with(wv, hoist_pattern, hoist_target, options).collect()
+}
+
+/*
+    `find_all_with` using the old exhaustive search, for comparison in
+    tests and benchmarks only
+ */
+#[doc(hidden)]
+pub fn find_all_exhaustive(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, options: MatchOptions) -> Vec<HashMap<EntityId, EntityId>> {
     let seed = HashMap::default();
-    if let Some(search_space) = prepare_search_space(wv, hoist_pattern, hoist_target, &seed) {
-        generate_products(wv, &search_space, seed)
+    if let Some(search_space) = prepare_search_space(wv, hoist_pattern, hoist_target, &seed, options) {
+        exhaustive_products(wv, &search_space, seed)
     } else {
         vec![]
     }
 }
 
-pub fn find_one(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId) -> Option<HashMap<EntityId, EntityId>> {
+#[doc(hidden)]
+pub fn find_one_exhaustive(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, options: MatchOptions) -> Option<HashMap<EntityId, EntityId>> {
     let seed = HashMap::default();
-    if let Some(search_space) = prepare_search_space(wv, hoist_pattern, hoist_target, &seed) {
-        generate_single_product(wv, &search_space, seed)
+    if let Some(search_space) = prepare_search_space(wv, hoist_pattern, hoist_target, &seed, options) {
+        exhaustive_single_product(wv, &search_space, seed)
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+pub fn find_one(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId) -> Option<HashMap<EntityId, EntityId>> {
+    find_one_with(wv, hoist_pattern, hoist_target, MatchOptions::default())
+}
+
+pub fn find_one_with(wv: &Weave, hoist_pattern: EntityId, hoist_target: EntityId, options: MatchOptions) -> Option<HashMap<EntityId, EntityId>> {
+    matches_with(wv, hoist_pattern, hoist_target, options).next()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq, Ne, Lt, Le, Gt, Ge,
+    Matches,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+            Comparison::Matches => "~",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Comparison> {
+        [ Comparison::Eq, Comparison::Ne, Comparison::Lt, Comparison::Le, Comparison::Gt, Comparison::Ge, Comparison::Matches ]
+            .into_iter().find(|c| c.symbol() == symbol)
+    }
+}
+
+/*
+    Only entities whose `component.field` compares to the value the given
+    way match the pattern entity; entities without the component don't.
+    Ints and Floats compare with each other, strings compare by their
+    characters and enum variants in the order they were declared in.
+ */
+pub fn require_value(wv: &mut Weave, entity: EntityId, component: &str, field: &str, op: Comparison, value: DataValue) {
+    let value = serde_json::to_string(&value).expect("Fields can't stringify");
+    annotate(wv, entity, "Where", &[
+        DataValue::String(component.to_string()),
+        DataValue::String(field.to_string()),
+        DataValue::String(op.symbol().to_string()),
+        DataValue::String(value),
+    ]);
+}
+
+/*
+    Requires `min <= component.field <= max`
+ */
+pub fn require_range(wv: &mut Weave, entity: EntityId, component: &str, field: &str, min: DataValue, max: DataValue) {
+    require_value(wv, entity, component, field, Comparison::Ge, min);
+    require_value(wv, entity, component, field, Comparison::Le, max);
+}
+
+/*
+    Requires the String (or enum variant) in `component.field` to match
+    the regular expression somewhere
+ */
+pub fn try_require_match(wv: &mut Weave, entity: EntityId, component: &str, field: &str, pattern: &str) -> Result<(), regex::Error> {
+    Regex::new(pattern)?;
+    require_value(wv, entity, component, field, Comparison::Matches, DataValue::String(pattern.to_string()));
+    Ok(())
+}
+
+pub fn require_match(wv: &mut Weave, entity: EntityId, component: &str, field: &str, pattern: &str) {
+    try_require_match(wv, entity, component, field, pattern).unwrap()
+}
+
+#[derive(Debug)]
+pub(crate) struct Predicate {
+    component: String,
+    field: String,
+    op: Comparison,
+    value: DataValue,
+    regex: Option<Regex>,
+}
+
+impl Predicate {
+    pub(crate) fn read(wv: &Weave, mark: EntityId) -> Predicate {
+        let fields = wv.get_component(mark, "Where").to_values();
+        let [ DataValue::String(component), DataValue::String(field), DataValue::String(op), DataValue::String(value) ] = &fields[..] else {
+            panic!("Where annotation isn't four strings!");
+        };
+
+        let op = Comparison::from_symbol(op).expect("Unknown comparison in Where annotation");
+        let value: DataValue = serde_json::from_str(value).expect("Where value can't be parsed");
+        let regex = match (&op, &value) {
+            (Comparison::Matches, DataValue::String(pattern)) => Regex::new(pattern).ok(),
+            _ => None,
+        };
+
+        Predicate { component: component.clone(), field: field.clone(), op, value, regex }
+    }
+
+    pub(crate) fn holds(&self, wv: &Weave, entity: EntityId) -> bool {
+        let Ok(actual) = wv.try_get_component_field(entity, &self.component, &self.field) else {
+            return false;
+        };
+
+        if self.op == Comparison::Matches {
+            return match (actual, &self.regex) {
+                (DataRef::String(s), Some(regex)) | (DataRef::Enum(_, s), Some(regex)) => regex.is_match(s),
+                _ => false,
+            };
+        }
+
+        let actual = actual.to_value();
+        let equal = actual == self.value;
+        match (self.op, compare(wv, &actual, &self.value)) {
+            (Comparison::Eq, ord) => equal || ord == Some(Ordering::Equal),
+            (Comparison::Ne, ord) => !equal && ord != Some(Ordering::Equal),
+            (Comparison::Lt, Some(ord)) => ord == Ordering::Less,
+            (Comparison::Le, Some(ord)) => ord != Ordering::Greater,
+            (Comparison::Gt, Some(ord)) => ord == Ordering::Greater,
+            (Comparison::Ge, Some(ord)) => ord != Ordering::Less,
+            _ => false,
+        }
+    }
+}
+
+fn compare(wv: &Weave, a: &DataValue, b: &DataValue) -> Option<Ordering> {
+    let variant = |datatype: &str, variant: &str| wv.get_enum_variants(datatype)
+        .and_then(|vs| vs.iter().position(|v| v == variant));
+
+    match (a, b) {
+        (DataValue::Int(a), DataValue::Int(b)) => Some(a.cmp(b)),
+        (DataValue::Int(a), DataValue::Float(b)) => (*a as f64).partial_cmp(b),
+        (DataValue::Float(a), DataValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (DataValue::Float(a), DataValue::Float(b)) => a.partial_cmp(b),
+        (DataValue::String(a), DataValue::String(b)) => Some(a.cmp(b)),
+        (DataValue::Bool(a), DataValue::Bool(b)) => Some(a.cmp(b)),
+        (DataValue::Entity(a), DataValue::Entity(b)) => Some(a.cmp(b)),
+        (DataValue::Enum(datatype, a), DataValue::Enum(_, b) | DataValue::String(b)) =>
+            Some(variant(datatype, a)?.cmp(&variant(datatype, b)?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use crate::core::{DataField, DataValue, Datatype, EntityId};
+
+pub(crate) type StringId = usize;
+
+/*
+    Strings used in component fields are stored once and referred to by id.
+    Every string is kept with a trailing NUL so that the FFI can hand out
+    pointers into the interner without copying. Each string counts the
+    fields using it, and is dropped (its id reused) once none do.
+ */
+#[derive(Default)]
+pub(crate) struct Interner {
+    ids: HashMap<Box<str>, StringId>,
+    strings: Vec<Box<str>>,
+    uses: Vec<usize>,
+    free: Vec<StringId>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, s: &str) -> StringId {
+        if let Some(&id) = self.ids.get(s) {
+            self.uses[id] += 1;
+            return id;
+        }
+
+        let mut stored = String::with_capacity(s.len() + 1);
+        stored.push_str(s);
+        stored.push('\0');
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.strings[id] = stored.into_boxed_str();
+                self.uses[id] = 1;
+                id
+            }
+            None => {
+                self.strings.push(stored.into_boxed_str());
+                self.uses.push(1);
+                self.strings.len() - 1
+            }
+        };
+        self.ids.insert(s.into(), id);
+        id
+    }
+
+    pub(crate) fn release(&mut self, id: StringId) {
+        self.uses[id] -= 1;
+        if self.uses[id] == 0 {
+            let stored = std::mem::take(&mut self.strings[id]);
+            self.ids.remove(&stored[..stored.len() - 1]);
+            self.free.push(id);
+        }
+    }
+
+    /*
+        Number of distinct strings in use
+     */
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub(crate) fn get(&self, id: StringId) -> &str {
+        let s = &self.strings[id];
+        &s[..s.len() - 1]
+    }
+
+    pub(crate) fn get_nul_terminated(&self, id: StringId) -> *const u8 {
+        self.strings[id].as_ptr()
+    }
+}
+
+/*
+    Borrowed view of a single field value
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataRef<'a> {
+    Entity(EntityId),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    List(DataSeq<'a>),
+    Optional(DataSeq<'a>),
+    Enum(&'a str, &'a str),
+    Vec2([f64; 2]),
+    Vec3([f64; 3]),
+    Vec4([f64; 4]),
+}
+
+impl DataRef<'_> {
+    pub fn to_value(&self) -> DataValue {
+        match self {
+            DataRef::Entity(e) => DataValue::Entity(*e),
+            DataRef::Int(i) => DataValue::Int(*i),
+            DataRef::Float(f) => DataValue::Float(*f),
+            DataRef::Bool(b) => DataValue::Bool(*b),
+            DataRef::String(s) => DataValue::String(s.to_string()),
+            DataRef::Bytes(b) => DataValue::Bytes(b.to_vec()),
+            DataRef::List(items) => DataValue::List(items.datatype(), items.to_values()),
+            DataRef::Optional(item) =>
+                DataValue::Optional(item.datatype(), item.get(0).map(|v| Box::new(v.to_value()))),
+            DataRef::Enum(name, variant) => DataValue::Enum(name.to_string(), variant.to_string()),
+            DataRef::Vec2(v) => DataValue::Vec2(*v),
+            DataRef::Vec3(v) => DataValue::Vec3(*v),
+            DataRef::Vec4(v) => DataValue::Vec4(*v),
+        }
+    }
+
+    /*
+        Entities the value refers to, including those inside lists and
+        optionals
+     */
+    pub fn entities(&self) -> Vec<EntityId> {
+        match self {
+            DataRef::Entity(e) => vec![ *e ],
+            DataRef::List(items) | DataRef::Optional(items) => items.iter().flat_map(|v| v.entities()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/*
+    Borrowed items of a list field, or the item of an optional one (an
+    optional holds at most one item)
+ */
+#[derive(Clone, Copy)]
+pub struct DataSeq<'a> {
+    column: &'a Column,
+    strings: &'a Interner,
+}
+
+impl<'a> DataSeq<'a> {
+    pub fn len(&self) -> usize {
+        self.column.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.column.len() == 0
+    }
+
+    pub fn datatype(&self) -> Datatype {
+        self.column.datatype()
+    }
+
+    pub fn get(&self, index: usize) -> Option<DataRef<'a>> {
+        (index < self.len()).then(|| self.column.get(index, self.strings))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = DataRef<'a>> + 'a {
+        let (column, strings) = (self.column, self.strings);
+        (0..column.len()).map(move |i| column.get(i, strings))
+    }
+
+    pub fn to_values(&self) -> Vec<DataValue> {
+        self.iter().map(|v| v.to_value()).collect()
+    }
+}
+
+impl PartialEq for DataSeq<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.datatype() == other.datatype() && self.iter().eq(other.iter())
+    }
+}
+
+impl std::fmt::Debug for DataSeq<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/*
+    Lists and optionals keep one small column per row, built from an empty
+    template column of the item type, so their items stay typed and
+    contiguous as well
+ */
+pub(crate) enum Column {
+    Entity(Vec<EntityId>),
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+    String(Vec<StringId>),
+    Bytes(Vec<Box<[u8]>>),
+    List(Box<Column>, Vec<Column>),
+    Optional(Box<Column>, Vec<Column>),
+    Enum(String, Vec<String>, Vec<u32>),
+    Vec2(Vec<[f64; 2]>),
+    Vec3(Vec<[f64; 3]>),
+    Vec4(Vec<[f64; 4]>),
+}
+
+impl Column {
+    fn new(datatype: &Datatype, enums: &HashMap<String, Vec<String>>) -> Self {
+        match datatype {
+            Datatype::Entity => Column::Entity(vec![]),
+            Datatype::Int => Column::Int(vec![]),
+            Datatype::Float => Column::Float(vec![]),
+            Datatype::Bool => Column::Bool(vec![]),
+            Datatype::String => Column::String(vec![]),
+            Datatype::Bytes => Column::Bytes(vec![]),
+            Datatype::List(item) => Column::List(Box::new(Column::new(item, enums)), vec![]),
+            Datatype::Optional(item) => Column::Optional(Box::new(Column::new(item, enums)), vec![]),
+            Datatype::Enum(name) =>
+                Column::Enum(name.clone(), enums.get(name).cloned().unwrap_or_default(), vec![]),
+            Datatype::Vec2 => Column::Vec2(vec![]),
+            Datatype::Vec3 => Column::Vec3(vec![]),
+            Datatype::Vec4 => Column::Vec4(vec![]),
+        }
+    }
+
+    fn empty_like(&self) -> Self {
+        match self {
+            Column::Entity(_) => Column::Entity(vec![]),
+            Column::Int(_) => Column::Int(vec![]),
+            Column::Float(_) => Column::Float(vec![]),
+            Column::Bool(_) => Column::Bool(vec![]),
+            Column::String(_) => Column::String(vec![]),
+            Column::Bytes(_) => Column::Bytes(vec![]),
+            Column::List(item, _) => Column::List(Box::new(item.empty_like()), vec![]),
+            Column::Optional(item, _) => Column::Optional(Box::new(item.empty_like()), vec![]),
+            Column::Enum(name, variants, _) => Column::Enum(name.clone(), variants.clone(), vec![]),
+            Column::Vec2(_) => Column::Vec2(vec![]),
+            Column::Vec3(_) => Column::Vec3(vec![]),
+            Column::Vec4(_) => Column::Vec4(vec![]),
+        }
+    }
+
+    fn datatype(&self) -> Datatype {
+        match self {
+            Column::Entity(_) => Datatype::Entity,
+            Column::Int(_) => Datatype::Int,
+            Column::Float(_) => Datatype::Float,
+            Column::Bool(_) => Datatype::Bool,
+            Column::String(_) => Datatype::String,
+            Column::Bytes(_) => Datatype::Bytes,
+            Column::List(item, _) => Datatype::List(Box::new(item.datatype())),
+            Column::Optional(item, _) => Datatype::Optional(Box::new(item.datatype())),
+            Column::Enum(name, _, _) => Datatype::Enum(name.clone()),
+            Column::Vec2(_) => Datatype::Vec2,
+            Column::Vec3(_) => Datatype::Vec3,
+            Column::Vec4(_) => Datatype::Vec4,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Column::Entity(c) => c.len(),
+            Column::Int(c) => c.len(),
+            Column::Float(c) => c.len(),
+            Column::Bool(c) => c.len(),
+            Column::String(c) => c.len(),
+            Column::Bytes(c) => c.len(),
+            Column::List(_, c) | Column::Optional(_, c) => c.len(),
+            Column::Enum(_, _, c) => c.len(),
+            Column::Vec2(c) => c.len(),
+            Column::Vec3(c) => c.len(),
+            Column::Vec4(c) => c.len(),
+        }
+    }
+
+    fn accepts(&self, value: &DataValue) -> bool {
+        self.datatype() == value.datatype()
+    }
+
+    fn build(template: &Column, items: &[DataValue], strings: &mut Interner) -> Column {
+        let mut column = template.empty_like();
+        for item in items {
+            column.push(item, strings);
+        }
+        column
+    }
+
+    fn variant_index(variants: &[String], variant: &str) -> u32 {
+        variants.iter().position(|v| v == variant).expect("enum variant checked before push") as u32
+    }
+
+    fn push(&mut self, value: &DataValue, strings: &mut Interner) {
+        match (self, value) {
+            (Column::Entity(c), DataValue::Entity(e)) => c.push(*e),
+            (Column::Int(c), DataValue::Int(i)) => c.push(*i),
+            (Column::Float(c), DataValue::Float(f)) => c.push(*f),
+            (Column::Bool(c), DataValue::Bool(b)) => c.push(*b),
+            (Column::String(c), DataValue::String(s)) => c.push(strings.intern(s)),
+            (Column::Bytes(c), DataValue::Bytes(b)) => c.push(b.clone().into_boxed_slice()),
+            (Column::List(t, c), DataValue::List(_, items)) => c.push(Column::build(t, items, strings)),
+            (Column::Optional(t, c), DataValue::Optional(_, item)) => {
+                let items = item.iter().map(|v| (**v).clone()).collect::<Vec<_>>();
+                c.push(Column::build(t, &items, strings))
+            }
+            (Column::Enum(_, variants, c), DataValue::Enum(_, v)) => c.push(Column::variant_index(variants, v)),
+            (Column::Vec2(c), DataValue::Vec2(v)) => c.push(*v),
+            (Column::Vec3(c), DataValue::Vec3(v)) => c.push(*v),
+            (Column::Vec4(c), DataValue::Vec4(v)) => c.push(*v),
+            _ => unreachable!("column type checked before push"),
+        }
+    }
+
+    fn set(&mut self, row: usize, value: &DataValue, strings: &mut Interner) {
+        match (self, value) {
+            (Column::Entity(c), DataValue::Entity(e)) => c[row] = *e,
+            (Column::Int(c), DataValue::Int(i)) => c[row] = *i,
+            (Column::Float(c), DataValue::Float(f)) => c[row] = *f,
+            (Column::Bool(c), DataValue::Bool(b)) => c[row] = *b,
+            (Column::String(c), DataValue::String(s)) => {
+                let id = strings.intern(s);
+                strings.release(std::mem::replace(&mut c[row], id));
+            }
+            (Column::Bytes(c), DataValue::Bytes(b)) => c[row] = b.clone().into_boxed_slice(),
+            (Column::List(t, c), DataValue::List(_, items)) => {
+                let items = Column::build(t, items, strings);
+                std::mem::replace(&mut c[row], items).release(strings);
+            }
+            (Column::Optional(t, c), DataValue::Optional(_, item)) => {
+                let items = item.iter().map(|v| (**v).clone()).collect::<Vec<_>>();
+                let items = Column::build(t, &items, strings);
+                std::mem::replace(&mut c[row], items).release(strings);
+            }
+            (Column::Enum(_, variants, c), DataValue::Enum(_, v)) => c[row] = Column::variant_index(variants, v),
+            (Column::Vec2(c), DataValue::Vec2(v)) => c[row] = *v,
+            (Column::Vec3(c), DataValue::Vec3(v)) => c[row] = *v,
+            (Column::Vec4(c), DataValue::Vec4(v)) => c[row] = *v,
+            _ => unreachable!("column type checked before set"),
+        }
+    }
+
+    /*
+        Lets go of every string in the column
+     */
+    fn release(&self, strings: &mut Interner) {
+        match self {
+            Column::String(c) => c.iter().for_each(|id| strings.release(*id)),
+            Column::List(_, c) | Column::Optional(_, c) => c.iter().for_each(|items| items.release(strings)),
+            _ => {}
+        }
+    }
+
+    fn swap_remove(&mut self, row: usize, strings: &mut Interner) {
+        match self {
+            Column::String(c) => strings.release(c[row]),
+            Column::List(_, c) | Column::Optional(_, c) => c[row].release(strings),
+            _ => {}
+        }
+
+        match self {
+            Column::Entity(c) => { c.swap_remove(row); }
+            Column::Int(c) => { c.swap_remove(row); }
+            Column::Float(c) => { c.swap_remove(row); }
+            Column::Bool(c) => { c.swap_remove(row); }
+            Column::String(c) => { c.swap_remove(row); }
+            Column::Bytes(c) => { c.swap_remove(row); }
+            Column::List(_, c) | Column::Optional(_, c) => { c.swap_remove(row); }
+            Column::Enum(_, _, c) => { c.swap_remove(row); }
+            Column::Vec2(c) => { c.swap_remove(row); }
+            Column::Vec3(c) => { c.swap_remove(row); }
+            Column::Vec4(c) => { c.swap_remove(row); }
+        }
+    }
+
+    fn get<'a>(&'a self, row: usize, strings: &'a Interner) -> DataRef<'a> {
+        match self {
+            Column::Entity(c) => DataRef::Entity(c[row]),
+            Column::Int(c) => DataRef::Int(c[row]),
+            Column::Float(c) => DataRef::Float(c[row]),
+            Column::Bool(c) => DataRef::Bool(c[row]),
+            Column::String(c) => DataRef::String(strings.get(c[row])),
+            Column::Bytes(c) => DataRef::Bytes(&c[row]),
+            Column::List(_, c) => DataRef::List(DataSeq { column: &c[row], strings }),
+            Column::Optional(_, c) => DataRef::Optional(DataSeq { column: &c[row], strings }),
+            Column::Enum(name, variants, c) => DataRef::Enum(name, &variants[c[row] as usize]),
+            Column::Vec2(c) => DataRef::Vec2(c[row]),
+            Column::Vec3(c) => DataRef::Vec3(c[row]),
+            Column::Vec4(c) => DataRef::Vec4(c[row]),
+        }
+    }
+
+    /*
+        Points at the value for fixed-size types, at the first byte of a
+        string or bytes field, and at the first item of a list or optional
+        (null when it has none)
+     */
+    pub(crate) fn get_ptr(&self, row: usize, strings: &Interner) -> *const u8 {
+        match self {
+            Column::Entity(c) => &c[row] as *const _ as *const u8,
+            Column::Int(c) => &c[row] as *const _ as *const u8,
+            Column::Float(c) => &c[row] as *const _ as *const u8,
+            Column::Bool(c) => &c[row] as *const _ as *const u8,
+            Column::String(c) => strings.get_nul_terminated(c[row]),
+            Column::Bytes(c) => c[row].as_ptr(),
+            Column::List(_, c) | Column::Optional(_, c) => c[row].get_ptr_checked(0, strings),
+            Column::Enum(_, _, c) => &c[row] as *const _ as *const u8,
+            Column::Vec2(c) => c[row].as_ptr() as *const u8,
+            Column::Vec3(c) => c[row].as_ptr() as *const u8,
+            Column::Vec4(c) => c[row].as_ptr() as *const u8,
+        }
+    }
+
+    /*
+        Number of items in a bytes, list or optional field; 1 for anything else
+     */
+    pub(crate) fn get_len(&self, row: usize) -> usize {
+        match self {
+            Column::Bytes(c) => c[row].len(),
+            Column::List(_, c) | Column::Optional(_, c) => c[row].len(),
+            _ => 1,
+        }
+    }
+
+    fn get_ptr_checked(&self, row: usize, strings: &Interner) -> *const u8 {
+        if row < self.len() { self.get_ptr(row, strings) } else { std::ptr::null() }
+    }
+
+    pub(crate) fn get_item(&self, row: usize, item: usize, strings: &Interner) -> *const u8 {
+        match self {
+            Column::Bytes(c) => c[row].get(item).map_or(std::ptr::null(), |b| b as *const u8),
+            Column::List(_, c) | Column::Optional(_, c) => c[row].get_ptr_checked(item, strings),
+            _ if item == 0 => self.get_ptr(row, strings),
+            _ => std::ptr::null(),
+        }
+    }
+}
+
+/*
+    All instances of one datatype, one column per field. Rows are kept
+    dense: removing a row moves the last one into its place.
+ */
+pub(crate) struct ComponentStore {
+    pub(crate) rows: HashMap<EntityId, usize>,
+    pub(crate) entities: Vec<EntityId>,
+    pub(crate) columns: Vec<Column>,
+}
+
+impl ComponentStore {
+    pub(crate) fn new(fields: &[DataField], enums: &HashMap<String, Vec<String>>) -> Self {
+        ComponentStore {
+            rows: HashMap::new(),
+            entities: vec![],
+            columns: fields.iter().map(|f| Column::new(&f.datatype, enums)).collect(),
+        }
+    }
+
+    pub(crate) fn accepts(&self, values: &[DataValue]) -> bool {
+        values.len() == self.columns.len()
+            && self.columns.iter().zip(values).all(|(c, v)| c.accepts(v))
+    }
+
+    pub(crate) fn contains(&self, entity: EntityId) -> bool {
+        self.rows.contains_key(&entity)
+    }
+
+    pub(crate) fn insert(&mut self, entity: EntityId, values: &[DataValue], strings: &mut Interner) {
+        debug_assert!(self.accepts(values));
+        let row = self.entities.len();
+        for (column, value) in self.columns.iter_mut().zip(values) {
+            column.push(value, strings);
+        }
+        self.entities.push(entity);
+        self.rows.insert(entity, row);
+    }
+
+    pub(crate) fn set(&mut self, entity: EntityId, values: &[DataValue], strings: &mut Interner) {
+        debug_assert!(self.accepts(values));
+        match self.rows.get(&entity) {
+            Some(&row) => {
+                for (column, value) in self.columns.iter_mut().zip(values) {
+                    column.set(row, value, strings);
+                }
+            }
+            None => self.insert(entity, values, strings),
+        }
+    }
+
+    pub(crate) fn set_field(&mut self, entity: EntityId, index: usize, value: &DataValue, strings: &mut Interner) {
+        debug_assert!(self.columns[index].accepts(value));
+        let row = self.rows[&entity];
+        self.columns[index].set(row, value, strings);
+    }
+
+    pub(crate) fn remove(&mut self, entity: EntityId, strings: &mut Interner) -> bool {
+        if let Some(row) = self.rows.remove(&entity) {
+            for column in &mut self.columns {
+                column.swap_remove(row, strings);
+            }
+            self.entities.swap_remove(row);
+            if row < self.entities.len() {
+                self.rows.insert(self.entities[row], row);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn release(&self, strings: &mut Interner) {
+        self.columns.iter().for_each(|c| c.release(strings));
+    }
+}
+
+/*
+    A component as it sits in its store; reading fields doesn't copy
+    or decode anything
+ */
+#[derive(Clone, Copy)]
+pub struct ComponentRef<'a> {
+    pub(crate) store: &'a ComponentStore,
+    pub(crate) row: usize,
+    pub(crate) strings: &'a Interner,
+}
+
+impl<'a> ComponentRef<'a> {
+    pub fn len(&self) -> usize {
+        self.store.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.columns.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<DataRef<'a>> {
+        self.store.columns.get(index).map(|c| c.get(self.row, self.strings))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = DataRef<'a>> + 'a {
+        let (row, strings) = (self.row, self.strings);
+        self.store.columns.iter().map(move |c| c.get(row, strings))
+    }
+
+    pub fn to_values(&self) -> Vec<DataValue> {
+        self.iter().map(|v| v.to_value()).collect()
+    }
+
+    pub(crate) fn get_ptr(&self, index: usize) -> Option<*const u8> {
+        self.store.columns.get(index).map(|c| c.get_ptr(self.row, self.strings))
+    }
+
+    pub(crate) fn get_len(&self, index: usize) -> Option<usize> {
+        self.store.columns.get(index).map(|c| c.get_len(self.row))
+    }
+
+    pub(crate) fn get_item_ptr(&self, index: usize, item: usize) -> Option<*const u8> {
+        self.store.columns.get(index).map(|c| c.get_item(self.row, item, self.strings))
+    }
+}
+
+impl std::fmt::Debug for ComponentRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -1,11 +1,21 @@
 
 #[cfg(test)]
 mod tests {
-    use crate::core::{DataValue, Weave};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use crate::component::{Component, Entity};
+    use crate::core::{DataField, DataValue, Datatype, EntityId, GrowthPolicy, Migration, MotifKind, RefPolicy, Weave, WeaveError};
+    use crate::query::Query;
+    use crate::graph::{is_reachable, reachable, shortest_path, shortest_path_weighted, strongly_connected, topological_sort, transitive_closure, weakly_connected, GraphError, Scope};
+    use crate::hierarchy::{ancestors, descendants, hoist_tree, HoistTree};
+    use crate::io::{deserialize, serialize, try_deserialize, try_upgrade, upgrade, FORMAT_VERSION};
+    use crate::path::{query, Path, PathError};
     use crate::replace::{replace};
     use crate::traverse::{arrows_out, down, down_n, marks, next, prev, tethers, to_tgt, up, up_n};
-    use crate::search::{find_all, find_one, require_component};
-    use crate::shape::{annotate, hoist, markup};
+    use crate::traverse::{arrows_out_iter, down_iter, marks_iter, next_iter, tethers_iter, to_tgt_iter, up_iter};
+    use crate::search::{find_all, find_all_exhaustive, find_all_with, find_one, find_one_with, forbid, matches, MatchOptions, require_component, require_match, require_no_component, require_range, require_value, try_require_match, Comparison, StopReason};
+    use crate::shape::{annotate, get_annotation, hoist, markup};
+    use crate::storage::DataRef;
 
     #[test]
     fn delete_becomes_nil() {
@@ -25,8 +35,539 @@ mod tests {
         let c = w.new_knot();
         w.delete_cascade(c);
         let d = w.new_arrow(b, b);
-        assert_eq!(a, b);
-        assert_eq!(c, d);
+        assert_eq!(Weave::entity_index(a), Weave::entity_index(b));
+        assert_eq!(Weave::entity_index(c), Weave::entity_index(d));
+        assert_ne!(a, b);
+        assert_ne!(c, d);
+    }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        w.add_component(a, "With", &[ DataValue::String("a".to_string()) ]);
+        w.delete_cascade(a);
+        let b = w.new_knot();
+        assert_eq!(Weave::entity_generation(a), 0);
+        assert_eq!(Weave::entity_generation(b), 1);
+        assert!(w.is_valid(b));
+        assert!(!w.is_valid(a));
+        assert!(w.is_stale(a));
+        assert!(!w.is_stale(b));
+        assert_eq!(w.try_src(a), Err(WeaveError::StaleEntity(a)));
+        assert!(!w.has_component(b, "With"));
+        assert!(!w.has_component(a, "With"));
+    }
+
+    #[test]
+    fn serialization_remaps_handles() {
+        let mut w: Weave = Weave::new();
+        let stale = w.new_knot();
+        w.delete_cascade(stale);
+        let a = w.new_knot();
+        let b = w.new_knot();
+        w.new_arrow(a, b);
+        annotate(&mut w, b, "Identity", &[ DataValue::Entity(a) ]);
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a, b ]);
+        let bytes = serialize(&w, env);
+
+        let mut v: Weave = Weave::new();
+        let loaded = deserialize(&mut v, &bytes);
+        let entities = down(&v, loaded);
+        assert_eq!(entities.len(), 3);
+        let knots = entities.iter().filter(|&e| v.is_knot(*e)).cloned().collect::<Vec<_>>();
+        let arrow = *entities.iter().find(|&e| v.is_arrow(*e)).unwrap();
+        let (src, tgt) = (v.src(arrow), v.tgt(arrow));
+        assert!(knots.contains(&src) && knots.contains(&tgt));
+        let identity = get_annotation(&v, tgt, "Identity").unwrap();
+        assert_eq!(v.get_component(identity, "Identity").to_values(), vec![ DataValue::Entity(src) ]);
+    }
+
+    #[test]
+    fn builder_configures_weave() {
+        let mut w: Weave = Weave::builder()
+            .capacity(2)
+            .growth(GrowthPolicy::Linear(3))
+            .builtin_datatypes(false)
+            .build();
+        assert_eq!(w.get_datatype_field_count("Identity"), 0);
+        let ids = (0..4).map(|_| w.new_knot()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![ 0, 1, 2, 3 ]);
+        assert_eq!(w.identities.len(), 5);
+
+        let d: Weave = Weave::default();
+        assert_eq!(d.identities.len(), 1024);
+        assert_eq!(d.get_datatype_field_count("Identity"), 1);
+    }
+
+    #[test]
+    fn fallible_api_reports_errors() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        let b = w.new_knot();
+        w.delete_cascade(b);
+        assert_eq!(w.try_new_arrow(a, b), Err(WeaveError::StaleEntity(b)));
+        assert_eq!(w.try_src(b + 1), Err(WeaveError::InvalidEntity(b + 1)));
+        assert_eq!(w.try_tgt(Weave::NIL), Err(WeaveError::InvalidEntity(Weave::NIL)));
+        assert_eq!(w.try_change_src(a, b), Err(WeaveError::StaleEntity(b)));
+        assert_eq!(w.src(a), a);
+        assert!(!w.is_valid(Weave::NIL));
+
+        assert!(matches!(w.try_get_datatype_field("Nope", 0), Err(WeaveError::UnknownDatatype(_))));
+        assert!(matches!(w.try_get_datatype_field("With", 3), Err(WeaveError::FieldIndexOutOfRange { index: 3, .. })));
+        assert!(matches!(w.try_get_component(a, "With"), Err(WeaveError::ComponentMissing { .. })));
+
+        w.add_component(a, "With", &[ DataValue::String("x".to_string()) ]);
+        assert_eq!(w.try_get_component(a, "With").map(|c| c.to_values()), Ok(vec![ DataValue::String("x".to_string()) ]));
+    }
+
+    #[test]
+    fn components_are_stored_in_typed_columns() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Named", &[
+            DataField::new("name", Datatype::String),
+            DataField::new("hp", Datatype::Int),
+        ]);
+        let a = w.new_knot();
+        let b = w.new_knot();
+        w.add_component(a, "Named", &[ DataValue::String("door".to_string()), DataValue::Int(3) ]);
+        w.add_component(b, "Named", &[ DataValue::String("door".to_string()), DataValue::Int(5) ]);
+        assert_eq!(w.get_component(b, "Named").get(0), Some(DataRef::String("door")));
+        assert_eq!(w.get_component(b, "Named").get(1), Some(DataRef::Int(5)));
+        assert!(w.try_add_component(a, "Named", &[ DataValue::Int(1) ]).is_err());
+
+        w.remove_component(a, "Named");
+        assert!(!w.has_component(a, "Named"));
+        assert_eq!(w.get_component(b, "Named").to_values(),
+                   vec![ DataValue::String("door".to_string()), DataValue::Int(5) ]);
+
+        w.add_component(a, "Undeclared", &[ DataValue::Float(0.5) ]);
+        assert_eq!(w.get_datatype_field("Undeclared", 0).datatype, Datatype::Float);
+        assert_eq!(w.get_component(a, "Undeclared").get(0), Some(DataRef::Float(0.5)));
+    }
+
+    #[test]
+    fn components_are_validated_against_schema() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Health", &[ DataField::new("hp", Datatype::Int) ]);
+        let a = w.new_knot();
+        assert_eq!(w.try_add_component(a, "Health", &[]),
+                   Err(WeaveError::ArityMismatch { datatype: "Health".to_string(), expected: 1, found: 0 }));
+        assert_eq!(w.try_add_component(a, "Health", &[ DataValue::Float(1.0) ]),
+                   Err(WeaveError::FieldTypeMismatch {
+                       datatype: "Health".to_string(),
+                       field: "hp".to_string(),
+                       expected: Datatype::Int,
+                       found: Datatype::Float,
+                   }));
+        assert!(!w.has_component(a, "Health"));
+        assert!(w.try_add_component(a, "Health", &[ DataValue::Int(10) ]).is_ok());
+
+        let mut s: Weave = Weave::builder().strict_datatypes(true).build();
+        let b = s.new_knot();
+        assert_eq!(s.try_add_component(b, "Health", &[ DataValue::Int(10) ]),
+                   Err(WeaveError::UnknownDatatype("Health".to_string())));
+        s.set_strict_datatypes(false);
+        assert!(s.try_add_component(b, "Health", &[ DataValue::Int(10) ]).is_ok());
+    }
+
+    #[test]
+    fn unused_strings_are_released() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Name", &[
+            DataField::new("name", Datatype::String),
+            DataField::new("aliases", Datatype::List(Box::new(Datatype::String))),
+        ]);
+        let [ a, b ] = [ 0; 2 ].map(|_| w.new_knot());
+        for i in 0..100 {
+            let aliases = DataValue::List(Datatype::String, vec![ DataValue::String(format!("alias {}", i)) ]);
+            w.set_component(a, "Name", &[ DataValue::String(format!("name {}", i)), aliases ]);
+            w.update_component_field(a, "Name", "name", DataValue::String("shared".to_string()));
+        }
+        w.add_component(b, "Name", &[ DataValue::String("shared".to_string()), DataValue::List(Datatype::String, vec![]) ]);
+        assert_eq!(w.strings.len(), 2);
+
+        w.remove_component(a, "Name");
+        assert_eq!(w.strings.len(), 1);
+        assert_eq!(w.get_component_field(b, "Name", "name"), DataRef::String("shared"));
+        w.delete_cascade(b);
+        assert_eq!(w.strings.len(), 0);
+    }
+
+    #[test]
+    fn components_can_be_overwritten_and_updated() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Health", &[
+            DataField::new("hp", Datatype::Int),
+            DataField::new("label", Datatype::String),
+        ]);
+        let a = w.new_knot();
+        w.add_component(a, "Health", &[ DataValue::Int(10), DataValue::String("ok".to_string()) ]);
+        assert_eq!(w.try_add_component(a, "Health", &[ DataValue::Int(1), DataValue::String("ko".to_string()) ]),
+                   Err(WeaveError::ComponentExists { entity: a, datatype: "Health".to_string() }));
+        assert_eq!(w.get_component_field(a, "Health", "hp"), DataRef::Int(10));
+        markup(&mut w, a, "Health", &[ DataValue::Int(2), DataValue::String("again".to_string()) ]);
+        assert_eq!(w.get_component_field(a, "Health", "hp"), DataRef::Int(10));
+
+        w.set_component(a, "Health", &[ DataValue::Int(7), DataValue::String("hurt".to_string()) ]);
+        assert_eq!(w.get_component_field(a, "Health", "hp"), DataRef::Int(7));
+        assert_eq!(w.get_component_field(a, "Health", "label"), DataRef::String("hurt"));
+
+        w.update_component_field(a, "Health", "hp", DataValue::Int(3));
+        assert_eq!(w.get_component(a, "Health").to_values(),
+                   vec![ DataValue::Int(3), DataValue::String("hurt".to_string()) ]);
+        assert!(matches!(w.try_update_component_field(a, "Health", "mp", DataValue::Int(3)),
+                         Err(WeaveError::UnknownField { .. })));
+        assert!(matches!(w.try_update_component_field(a, "Health", "hp", DataValue::Bool(true)),
+                         Err(WeaveError::FieldTypeMismatch { .. })));
+
+        let b = w.new_knot();
+        assert!(matches!(w.try_update_component_field(b, "Health", "hp", DataValue::Int(3)),
+                         Err(WeaveError::ComponentMissing { .. })));
+        w.set_component(b, "Health", &[ DataValue::Int(1), DataValue::String("new".to_string()) ]);
+        assert_eq!(w.get_component_field(b, "Health", "hp"), DataRef::Int(1));
+    }
+
+    #[test]
+    fn archetypes_track_every_entity() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        let b = w.new_knot();
+        let c = w.new_knot();
+        w.add_component(a, "Position", &[ DataValue::Float(0.0) ]);
+        w.add_component(b, "Position", &[ DataValue::Float(1.0) ]);
+        w.add_component(b, "Frozen", &[]);
+        w.set_component(b, "Frozen", &[]);
+        assert_eq!(w.components_of(a), vec![ "Position".to_string() ]);
+        assert_eq!(w.components_of(b), vec![ "Position".to_string(), "Frozen".to_string() ]);
+        assert!(w.components_of(c).is_empty());
+
+        assert_eq!(w.entities_with(&[ "Position" ]), vec![ a, b ]);
+        assert_eq!(w.entities_with(&[ "Position", "Frozen" ]), vec![ b ]);
+        assert!(w.entities_with(&[ "Position", "Missing" ]).is_empty());
+        assert_eq!(w.entities_without(&[ "Frozen" ]), vec![ a, c ]);
+        assert_eq!(w.entities_without(&[ "Position" ]), vec![ c ]);
+
+        w.remove_component(b, "Frozen");
+        assert_eq!(w.components_of(b), vec![ "Position".to_string() ]);
+        assert_eq!(w.entities_without(&[ "Frozen" ]), vec![ a, b, c ]);
+
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a, b ]);
+        let mut v: Weave = Weave::new();
+        v.def_datatype("Position", &[ w.get_datatype_field("Position", 0) ]);
+        let loaded = deserialize(&mut v, &serialize(&w, env));
+        assert_eq!(v.entities_with(&[ "Position" ]).len(), 2);
+        assert!(down(&v, loaded).iter().all(|e| v.has_component(*e, "Position")));
+    }
+
+    #[test]
+    fn query_components_and_kinds() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        let b = w.new_knot();
+        let c = w.new_knot();
+        let d = w.new_arrow(a, b);
+        for (e, x) in [ (a, 0.0), (b, 1.0), (c, 2.0), (d, 3.0) ] {
+            w.add_component(e, "Position", &[ DataValue::Float(x) ]);
+            w.add_component(e, "Velocity", &[ DataValue::Float(1.0) ]);
+        }
+        w.add_component(b, "Frozen", &[]);
+        w.add_component(c, "Name", &[ DataValue::String("c".to_string()) ]);
+
+        let query = Query::new().with("Position").with("Velocity").without("Frozen").optional("Name");
+        let items = query.iter(&w).collect::<Vec<_>>();
+        assert_eq!(items.iter().map(|i| i.entity).collect::<Vec<_>>(), vec![ a, c, d ]);
+        assert_eq!(items[1].with[0].get(0), Some(DataRef::Float(2.0)));
+        assert!(items[0].optional[0].is_none());
+        assert_eq!(items[1].optional[0].unwrap().get(0), Some(DataRef::String("c")));
+
+        assert_eq!(query.clone().kind(MotifKind::Arrow).entities(&w), vec![ d ]);
+        assert_eq!(Query::new().with("Frozen").kind(MotifKind::Knot).entities(&w), vec![ b ]);
+        assert!(Query::new().with("Missing").entities(&w).is_empty());
+        assert_eq!(Query::new().without("Position").kind(MotifKind::Knot).entities(&w).len(), 0);
+    }
+
+    #[test]
+    fn datatype_ids_are_stable_and_checked() {
+        let mut w: Weave = Weave::new();
+        assert_eq!(w.get_datatype_id("Identity"), 0x94d63573d312fe9b);
+        assert_eq!(w.def_datatype("Door", &[]), 0x037cf773608ce6c9);
+
+        let forged = Weave::get_type_id("Window");
+        w.type_names.insert(forged, "Forged".to_string());
+        w.types.insert(forged, vec![]);
+        assert_eq!(w.try_def_datatype("Window", &[]),
+                   Err(WeaveError::DatatypeCollision { name: "Window".to_string(), existing: "Forged".to_string() }));
+        assert!(w.try_get_datatype_id("Window").is_err());
+        assert!(w.try_get_datatype_id("Forged").is_err());
+    }
+
+    #[test]
+    fn legacy_serialized_weaves_load_and_upgrade() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        w.add_component(a, "Door", &[ DataValue::Bool(true) ]);
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a ]);
+        let current = serialize(&w, env);
+
+        // legacy files have no header and arbitrary datatype ids
+        let mut legacy = current[16..].to_vec();
+        let id_at = legacy.len() - 8 - "[{\"Bool\":true}]".len() - 8;
+        legacy[id_at..id_at + 8].copy_from_slice(&12345u64.to_ne_bytes());
+
+        let mut v: Weave = Weave::new();
+        let loaded = deserialize(&mut v, &legacy);
+        assert_eq!(v.get_component(down(&v, loaded)[0], "Door").to_values(), vec![ DataValue::Bool(true) ]);
+
+        let upgraded = upgrade(&legacy);
+        assert_eq!(upgraded, current);
+        assert_eq!(upgrade(&current), current);
+    }
+
+    #[test]
+    fn bad_serialized_weaves_are_refused() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        w.add_component(a, "Door", &[ DataValue::Bool(true) ]);
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a ]);
+        let current = serialize(&w, env);
+
+        let mut future = current.clone();
+        future[8..16].copy_from_slice(&(FORMAT_VERSION + 1).to_ne_bytes());
+        let mut forged = current.clone();
+        let id_at = forged.len() - 8 - "[{\"Bool\":true}]".len() - 8;
+        forged[id_at..id_at + 8].copy_from_slice(&12345u64.to_ne_bytes());
+        let truncated = &current[..current.len() - 3];
+
+        let mut v: Weave = Weave::new();
+        let before = v.entities().count();
+        assert_eq!(try_deserialize(&mut v, &future), Err(WeaveError::UnsupportedFormat(FORMAT_VERSION + 1)));
+        assert_eq!(try_upgrade(&future), Err(WeaveError::UnsupportedFormat(FORMAT_VERSION + 1)));
+        assert!(matches!(try_deserialize(&mut v, &forged), Err(WeaveError::CorruptData(_))));
+        assert!(matches!(try_deserialize(&mut v, truncated), Err(WeaveError::CorruptData(_))));
+        assert!(matches!(try_upgrade(&truncated[16..]), Err(WeaveError::CorruptData(_))));
+        assert_eq!(v.entities().count(), before);
+
+        let mut strict: Weave = Weave::builder().strict_datatypes(true).build();
+        let before = strict.entities().count();
+        assert_eq!(try_deserialize(&mut strict, &current), Err(WeaveError::UnknownDatatype("Door".to_string())));
+        assert_eq!(strict.entities().count(), before);
+    }
+
+    #[test]
+    fn datatypes_can_be_redefined_and_removed() {
+        let mut w: Weave = Weave::new();
+        let fields = [ DataField::new("x", Datatype::Int) ];
+        w.def_datatype("Point", &fields);
+        let a = w.new_knot();
+        w.add_component(a, "Point", &[ DataValue::Int(3) ]);
+
+        assert!(w.try_def_datatype("Point", &fields).is_ok());
+        assert_eq!(w.try_def_datatype("Point", &[]), Err(WeaveError::DatatypeRedefined("Point".to_string())));
+
+        let migrations = [
+            Migration::AddField(DataField::new("y", Datatype::Int).with_default(DataValue::Int(0))),
+            Migration::RenameField { from: "x".to_string(), to: "px".to_string() },
+            Migration::MapField {
+                field: "px".to_string(),
+                datatype: Datatype::Float,
+                map: Box::new(|v| match v { DataValue::Int(i) => DataValue::Float(*i as f64), v => v.clone() }),
+            },
+        ];
+        w.redefine_datatype("Point", &migrations);
+        assert_eq!(w.get_component(a, "Point").to_values(), vec![ DataValue::Float(3.0), DataValue::Int(0) ]);
+        assert_eq!(w.get_component_field(a, "Point", "px"), DataRef::Float(3.0));
+
+        let bad = [ Migration::DropField("y".to_string()), Migration::DropField("z".to_string()) ];
+        assert!(matches!(w.try_redefine_datatype("Point", &bad), Err(WeaveError::UnknownField { .. })));
+        assert_eq!(w.get_component(a, "Point").len(), 2);
+        let no_default = [ Migration::AddField(DataField::new("z", Datatype::Int)) ];
+        assert!(matches!(w.try_redefine_datatype("Point", &no_default), Err(WeaveError::MissingField { .. })));
+        let undeclared = [ Migration::AddField(DataField::new("tags", Datatype::List(Box::new(Datatype::Enum("Tag".to_string()))))
+            .with_default(DataValue::List(Datatype::Enum("Tag".to_string()), vec![]))) ];
+        assert_eq!(w.try_redefine_datatype("Point", &undeclared), Err(WeaveError::UnknownDatatype("Tag".to_string())));
+        let unknown_enum = [ Migration::MapField {
+            field: "y".to_string(),
+            datatype: Datatype::Enum("Side".to_string()),
+            map: Box::new(|_| DataValue::Enum("Side".to_string(), "left".to_string())),
+        } ];
+        assert_eq!(w.try_redefine_datatype("Point", &unknown_enum), Err(WeaveError::UnknownDatatype("Side".to_string())));
+        assert_eq!(w.get_component(a, "Point").to_values(), vec![ DataValue::Float(3.0), DataValue::Int(0) ]);
+
+        w.undef_datatype("Point");
+        assert!(!w.has_component(a, "Point"));
+        assert!(w.components_of(a).is_empty());
+        assert!(w.try_get_datatype_id("Point").is_err());
+
+        assert_eq!(w.try_undef_datatype("With"), Err(WeaveError::BuiltinDatatype("With".to_string())));
+        assert!(matches!(w.try_redefine_datatype("Identity", &[]), Err(WeaveError::BuiltinDatatype(_))));
+        assert!(w.try_get_datatype_id("With").is_ok());
+    }
+
+    #[test]
+    fn composite_datatypes_round_trip() {
+        let mut w: Weave = Weave::new();
+        w.def_enum("Shape", &[ "Box", "Sphere" ]);
+        w.def_datatype("Body", &[
+            DataField::new("shape", Datatype::Enum("Shape".to_string())),
+            DataField::new("at", Datatype::Vec3),
+            DataField::new("touching", Datatype::List(Box::new(Datatype::Entity))),
+            DataField::new("owner", Datatype::Optional(Box::new(Datatype::String))),
+            DataField::new("blob", Datatype::Bytes),
+        ]);
+
+        let a = w.new_knot();
+        let b = w.new_knot();
+        let body = vec![
+            DataValue::Enum("Shape".to_string(), "Sphere".to_string()),
+            DataValue::Vec3([ 1.0, 2.0, 3.0 ]),
+            DataValue::List(Datatype::Entity, vec![ DataValue::Entity(b) ]),
+            DataValue::Optional(Datatype::String, None),
+            DataValue::Bytes(vec![ 1, 2, 3 ]),
+        ];
+        w.add_component(a, "Body", &body);
+
+        let component = w.get_component(a, "Body");
+        assert_eq!(component.get(0), Some(DataRef::Enum("Shape", "Sphere")));
+        assert_eq!(component.get(1), Some(DataRef::Vec3([ 1.0, 2.0, 3.0 ])));
+        assert!(matches!(component.get(2), Some(DataRef::List(items)) if items.get(0) == Some(DataRef::Entity(b))));
+        assert!(matches!(component.get(3), Some(DataRef::Optional(item)) if item.is_empty()));
+        assert_eq!(component.get(4), Some(DataRef::Bytes(&[ 1, 2, 3 ])));
+        assert_eq!(component.to_values(), body);
+
+        let mut wrong = body.clone();
+        wrong[0] = DataValue::Enum("Shape".to_string(), "Cone".to_string());
+        assert!(matches!(w.try_set_component(a, "Body", &wrong), Err(WeaveError::UnknownVariant { .. })));
+        wrong[0] = body[0].clone();
+        wrong[2] = DataValue::List(Datatype::Entity, vec![ DataValue::Int(1) ]);
+        assert!(matches!(w.try_set_component(a, "Body", &wrong), Err(WeaveError::FieldTypeMismatch { .. })));
+
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a, b ]);
+        let serialized = serialize(&w, env);
+        let mut v: Weave = Weave::new();
+        v.def_enum("Shape", &[ "Box", "Sphere" ]);
+        let loaded = deserialize(&mut v, &serialized);
+        let loaded_a = down(&v, loaded).into_iter().find(|e| v.has_component(*e, "Body")).unwrap();
+        let values = v.get_component(loaded_a, "Body").to_values();
+        assert_eq!(values[0], body[0]);
+        assert!(matches!(&values[2], DataValue::List(_, items)
+            if matches!(items[0], DataValue::Entity(e) if e != loaded_a && v.is_valid(e))));
+    }
+
+    #[test]
+    fn components_can_be_added_by_field_name() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Light", &[
+            DataField::new("color", Datatype::String).with_default(DataValue::String("white".to_string())),
+            DataField::new("lumens", Datatype::Int),
+        ]);
+        let a = w.new_knot();
+        w.add_component_named(a, "Light", &[ ("lumens", DataValue::Int(800)) ]);
+        assert_eq!(w.get_component(a, "Light").to_values(),
+                   vec![ DataValue::String("white".to_string()), DataValue::Int(800) ]);
+
+        let b = w.new_knot();
+        assert_eq!(w.try_add_component_named(b, "Light", &[ ("color", DataValue::String("red".to_string())) ]),
+                   Err(WeaveError::MissingField { datatype: "Light".to_string(), field: "lumens".to_string() }));
+        assert!(matches!(w.try_add_component_named(b, "Light", &[ ("watts", DataValue::Int(1)) ]),
+                         Err(WeaveError::UnknownField { .. })));
+        assert!(!w.has_component(b, "Light"));
+
+        let bad_default = DataField::new("on", Datatype::Bool).with_default(DataValue::Int(1));
+        assert!(matches!(w.try_def_datatype("Switch", &[ bad_default ]), Err(WeaveError::FieldTypeMismatch { .. })));
+
+        w.add_component_named(b, "Tag", &[ ("label", DataValue::String("lamp".to_string())) ]);
+        assert_eq!(w.get_component_field(b, "Tag", "label"), DataRef::String("lamp"));
+
+        // looking values up or failing to add them doesn't define anything
+        let twice = [ ("x", DataValue::Int(1)), ("x", DataValue::Int(2)) ];
+        assert!(w.try_get_component_values("Point", &[ ("x", DataValue::Int(1)) ]).is_ok());
+        assert!(matches!(w.try_add_component_named(b, "Point", &twice), Err(WeaveError::SchemaMismatch { .. })));
+        assert!(w.try_get_datatype_id("Point").is_err());
+        assert!(w.try_def_datatype("Point", &[ DataField::new("x", Datatype::Float) ]).is_ok());
+    }
+
+    #[test]
+    fn entity_references_follow_their_policy() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Follows", &[ DataField::new("leader", Datatype::Entity) ]);
+        w.def_datatype("Owned", &[ DataField::new("owner", Datatype::Entity).on_delete(RefPolicy::Cascade) ]);
+        w.def_datatype("Pinned", &[ DataField::new("to", Datatype::Entity).on_delete(RefPolicy::Restrict) ]);
+
+        let leader = w.new_knot();
+        let follower = w.new_knot();
+        let owned = w.new_knot();
+        let arrow = w.new_arrow(owned, owned);
+        w.add_component(follower, "Follows", &[ DataValue::Entity(leader) ]);
+        w.add_component(owned, "Owned", &[ DataValue::Entity(leader) ]);
+        assert_eq!(w.referrers(leader), vec![ follower, owned ]);
+
+        w.delete_cascade(leader);
+        assert_eq!(w.get_component(follower, "Follows").get(0), Some(DataRef::Entity(Weave::NIL)));
+        assert!(!w.is_valid(owned));
+        assert!(!w.is_valid(arrow));
+        assert!(w.referrers(leader).is_empty());
+
+        let anchor = w.new_knot();
+        let pin = w.new_knot();
+        w.add_component(pin, "Pinned", &[ DataValue::Entity(anchor) ]);
+        assert!(matches!(w.try_delete_orphan(anchor), Err(WeaveError::Referenced { .. })));
+        assert!(w.is_valid(anchor));
+
+        w.remove_component(pin, "Pinned");
+        assert!(w.referrers(anchor).is_empty());
+        w.delete_orphan(anchor);
+        assert!(!w.is_valid(anchor));
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Health {
+        hp: i64,
+        regen: Option<f64>,
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(name = "Squad")]
+    struct Team {
+        members: Vec<Entity>,
+        banner: String,
+    }
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Slot {
+        index: usize,
+    }
+
+    #[test]
+    fn derived_components() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        let b = w.new_knot();
+        w.insert(a, Health { hp: 10, regen: None });
+        w.insert(b, Team { members: vec![ Entity(a) ], banner: "red".to_string() });
+
+        assert_eq!(w.get_datatype_field(Health::NAME, 1), DataField::new("regen", Datatype::Optional(Box::new(Datatype::Float))));
+        assert_eq!(w.get::<Health>(a), Health { hp: 10, regen: None });
+        assert_eq!(w.get::<Team>(b).members, vec![ Entity(a) ]);
+        assert!(w.has_component(b, "Squad"));
+        assert_eq!(w.referrers(a), vec![ b ]);
+
+        w.update::<Health>(a, |h| { h.hp -= 3; h.regen = Some(0.5); });
+        assert_eq!(w.get::<Health>(a), Health { hp: 7, regen: Some(0.5) });
+        assert!(matches!(w.try_get::<Health>(b), Err(WeaveError::ComponentMissing { .. })));
+
+        let c = w.new_knot();
+        w.insert(b, Slot { index: c });
+        assert_eq!(w.get_datatype_field("Slot", 0).datatype, Datatype::Int);
+        assert!(w.referrers(c).is_empty());
+        w.delete_cascade(c);
+        assert!(w.is_valid(b));
+        assert_eq!(w.get::<Slot>(b), Slot { index: c });
     }
 
     #[test]
@@ -194,6 +735,180 @@ mod tests {
         assert_eq!(un, vec![ a ]);
     }
 
+    #[test]
+    fn lazy_traversals_match_collected_ones() {
+        let mut w: Weave = Weave::new();
+        let a = w.new_knot();
+        let x = w.new_knot();
+        let y = w.new_knot();
+        let xy = w.new_arrow(x, y);
+        hoist(&mut w, a, &[ x, y ]);
+
+        let sorted = |mut v: Vec<EntityId>| { v.sort(); v };
+        assert_eq!(sorted(down_iter(&w, a).collect()), sorted(down(&w, a)));
+        assert_eq!(up_iter(&w, x).collect::<Vec<_>>(), vec![ a ]);
+        assert_eq!(next_iter(&w, x).collect::<Vec<_>>(), next(&w, x));
+        assert_eq!(arrows_out_iter(&w, [ x, y ]).collect::<Vec<_>>(), vec![ xy ]);
+        assert_eq!(to_tgt_iter(&w, arrows_out_iter(&w, [ x ])).collect::<Vec<_>>(), vec![ y ]);
+        assert_eq!(sorted(tethers_iter(&w, [ a ]).collect()), sorted(tethers(&w, &[ a ])));
+        assert_eq!(sorted(marks_iter(&w, [ x, y ]).collect()), sorted(marks(&w, &[ x, y ])));
+    }
+
+    #[test]
+    fn traversals_and_serialization_are_deterministic() {
+        fn build() -> (Weave, EntityId) {
+            let mut w: Weave = Weave::new();
+            let env = w.new_knot();
+            let hub = w.new_knot();
+            let spokes = (0..16).map(|_| w.new_knot()).collect::<Vec<_>>();
+            for s in &spokes {
+                w.new_arrow(hub, *s);
+                w.new_arrow(*s, hub);
+                markup(&mut w, *s, "Spoke", &[ DataValue::Int(*s as i64) ]);
+            }
+            hoist(&mut w, env, &[ hub ]);
+            hoist(&mut w, env, &spokes);
+            (w, env)
+        }
+
+        let (w, env) = build();
+        let hub = down(&w, env)[0];
+        for ids in [ down(&w, env), arrows_out(&w, &[ hub ]), marks(&w, &down(&w, env)), next(&w, hub) ] {
+            assert!(ids.windows(2).all(|p| p[0] < p[1]));
+        }
+
+        let bytes = serialize(&w, env);
+        for _ in 0..4 {
+            let (v, env) = build();
+            assert_eq!(serialize(&v, env), bytes);
+        }
+    }
+
+    #[test]
+    fn graph_algorithms_follow_arrows() {
+        let mut w: Weave = Weave::new();
+        let [ a, b, c, d, e ] = [ 0; 5 ].map(|_| w.new_knot());
+        let ab = w.new_arrow(a, b);
+        let bc = w.new_arrow(b, c);
+        let ac = w.new_arrow(a, c);
+        let de = w.new_arrow(d, e);
+        w.def_datatype("Cost", &[ DataField::new("cost", Datatype::Float) ]);
+        w.add_component(ab, "Cost", &[ DataValue::Float(1.0) ]);
+        w.add_component(bc, "Cost", &[ DataValue::Float(1.5) ]);
+        w.add_component(ac, "Cost", &[ DataValue::Float(4.0) ]);
+
+        assert_eq!(reachable(&w, a, Scope::Weave), vec![ b, c ]);
+        assert!(!is_reachable(&w, c, a, Scope::Weave));
+        assert_eq!(shortest_path(&w, a, c, Scope::Weave), Some(vec![ a, c ]));
+        assert_eq!(shortest_path_weighted(&w, a, c, "Cost", Scope::Weave), Ok(Some((2.5, vec![ a, b, c ]))));
+        assert_eq!(shortest_path_weighted(&w, d, e, "Cost", Scope::Weave), Err(GraphError::MissingWeight(de)));
+        assert_eq!(weakly_connected(&w, Scope::Weave), vec![ vec![ a, b, c ], vec![ d, e ] ]);
+        assert_eq!(topological_sort(&w, Scope::Weave), Ok(vec![ a, b, c, d, e ]));
+        assert_eq!(transitive_closure(&w, Scope::Weave)[&b], vec![ c ]);
+
+        let ca = w.new_arrow(c, a);
+        assert_eq!(strongly_connected(&w, Scope::Weave), vec![ vec![ a, b, c ], vec![ d ], vec![ e ] ]);
+        assert_eq!(topological_sort(&w, Scope::Weave), Err(GraphError::Cycle(vec![ a, b, c ])));
+
+        // restricted to an environment, arrows leaving it don't count
+        let env = w.new_knot();
+        hoist(&mut w, env, &[ a, c, ca ]);
+        assert_eq!(reachable(&w, a, Scope::Env(env)), vec![ a, c ]);
+        assert_eq!(shortest_path(&w, b, c, Scope::Env(env)), None);
+        assert_eq!(topological_sort(&w, Scope::Env(env)), Err(GraphError::Cycle(vec![ a, c ])));
+    }
+
+    #[test]
+    fn nested_hoists_can_be_walked() {
+        let mut w: Weave = Weave::new();
+        let [ level, room, hall, chair, lamp ] = [ 0; 5 ].map(|_| w.new_knot());
+        hoist(&mut w, level, &[ room, hall ]);
+        hoist(&mut w, room, &[ chair, lamp ]);
+        hoist(&mut w, hall, &[ lamp ]);
+
+        assert_eq!(descendants(&w, level, Some(1)), down(&w, level));
+        assert_eq!(descendants(&w, level, None), vec![ room, hall, chair, lamp ]);
+        assert_eq!(ancestors(&w, lamp), vec![ room, hall, level ]);
+        let tree = hoist_tree(&w, level, None);
+        assert_eq!(tree.depth(), 2);
+        assert_eq!(tree.entities(), vec![ level, room, chair, lamp, hall, lamp ]);
+
+        // hoisting into itself or an ancestor doesn't loop
+        hoist(&mut w, chair, &[ chair, level ]);
+        assert_eq!(descendants(&w, level, None), vec![ room, hall, chair, lamp ]);
+        assert_eq!(ancestors(&w, level), vec![ chair, room ]);
+        assert_eq!(hoist_tree(&w, chair, None).entities(), vec![ chair, level, room, lamp, hall, lamp ]);
+        assert_eq!(hoist_tree(&w, lamp, None), HoistTree { entity: lamp, children: vec![] });
+        assert_eq!(hoist_tree(&w, chair, Some(1)).entities(), vec![ chair, level ]);
+    }
+
+    #[test]
+    fn path_expressions_chain_moves() {
+        let mut w: Weave = Weave::new();
+        let [ room, door, window, hall ] = [ 0; 4 ].map(|_| w.new_knot());
+        hoist(&mut w, room, &[ door, window, hall ]);
+        markup(&mut w, door, "With", &[ DataValue::String("Door".to_string()) ]);
+        markup(&mut w, window, "With", &[ DataValue::String("Window pane".to_string()) ]);
+        w.def_datatype("Size", &[ DataField::new("w", Datatype::Int), DataField::new("h", Datatype::Int) ]);
+        markup(&mut w, window, "Size", &[ DataValue::Int(2), DataValue::Int(3) ]);
+
+        let all = to_tgt(&w, &to_tgt(&w, &arrows_out(&w, &tethers(&w, &[ room ]))));
+        assert_eq!(query(&w, &[ room ], "tethers.arrows_out.tgt.tgt"), Ok(all));
+        assert_eq!(query(&w, &[ room ], "down[With=Door]"), Ok(vec![ door ]));
+        assert_eq!(query(&w, &[ room ], "down[With=\"Window pane\"]"), Ok(vec![ window ]));
+        assert_eq!(query(&w, &[ room ], "down[With][With!=Door]"), Ok(vec![ window ]));
+        assert_eq!(query(&w, &[ room ], "down[!With]"), Ok(vec![ hall ]));
+        assert_eq!(query(&w, &[ room ], " down [Size.h=3] . up "), Ok(vec![ room ]));
+
+        let path = Path::parse("down[Size.w!=1][!With]").unwrap();
+        assert_eq!(Path::parse(&path.to_string()).unwrap().to_string(), path.to_string());
+        assert_eq!(query(&w, &[ room ], "down.sideways"), Err(PathError::UnknownMove { at: 5, name: "sideways".to_string() }));
+        assert_eq!(query(&w, &[ room ], "down[With"), Err(PathError::Expected { at: 9, expected: "']'" }));
+    }
+
+    #[test]
+    fn patterns_can_require_field_values() {
+        let mut w: Weave = Weave::new();
+        w.def_datatype("Health", &[ DataField::new("hp", Datatype::Int) ]);
+        w.def_datatype("Name", &[ DataField::new("value", Datatype::String) ]);
+
+        let target = w.new_knot();
+        let mut things = vec![];
+        for (hp, name) in [ (4, "door"), (12, "door"), (7, "trapdoor"), (3, "window") ] {
+            let e = w.new_knot();
+            markup(&mut w, e, "Health", &[ DataValue::Int(hp) ]);
+            markup(&mut w, e, "Name", &[ DataValue::String(name.to_string()) ]);
+            things.push(e);
+        }
+        hoist(&mut w, target, &things);
+
+        let matches = |w: &mut Weave, require: &dyn Fn(&mut Weave, EntityId)| {
+            let pattern = w.new_knot();
+            let p = w.new_knot();
+            require(w, p);
+            hoist(w, pattern, &[ p ]);
+            let mut found = find_all(w, pattern, target).iter().map(|m| m[&p]).collect::<Vec<_>>();
+            found.sort();
+            found
+        };
+
+        assert_eq!(matches(&mut w, &|w, p| require_value(w, p, "Health", "hp", Comparison::Lt, DataValue::Int(10))),
+                   vec![ things[0], things[2], things[3] ]);
+        assert_eq!(matches(&mut w, &|w, p| require_value(w, p, "Name", "value", Comparison::Eq, DataValue::String("door".to_string()))),
+                   vec![ things[0], things[1] ]);
+        assert_eq!(matches(&mut w, &|w, p| require_range(w, p, "Health", "hp", DataValue::Float(4.0), DataValue::Int(12))),
+                   vec![ things[0], things[1], things[2] ]);
+        assert_eq!(matches(&mut w, &|w, p| {
+            require_match(w, p, "Name", "value", "door$");
+            require_value(w, p, "Health", "hp", Comparison::Ne, DataValue::Int(12));
+        }), vec![ things[0], things[2] ]);
+        assert!(matches(&mut w, &|w, p| {
+            require_no_component(w, p, "Name");
+            require_value(w, p, "Health", "hp", Comparison::Ge, DataValue::Int(0));
+        }).is_empty());
+        assert!(try_require_match(&mut w, things[0], "Name", "value", "(").is_err());
+    }
+
     #[test]
     fn test_pattern_match() {
         let mut w: Weave = Weave::new();
@@ -273,6 +988,213 @@ mod tests {
         println!("{:?}", matching);
     }
 
+    #[test]
+    fn negative_conditions_reject_matches() {
+        let mut w: Weave = Weave::new();
+
+        // doors with no arrow to anything locked
+        let pattern = w.new_knot();
+        let door = w.new_knot();
+        require_component(&mut w, door, "Door");
+        hoist(&mut w, pattern, &[ door ]);
+        let nac = w.new_knot();
+        let lock = w.new_knot();
+        require_component(&mut w, lock, "Locked");
+        let door_lock = w.new_arrow(door, lock);
+        hoist(&mut w, nac, &[ door, lock, door_lock ]);
+        forbid(&mut w, pattern, nac);
+
+        let target = w.new_knot();
+        let [ locked, open, key ] = [ 0; 3 ].map(|_| w.new_knot());
+        markup(&mut w, open, "Door", &[]);
+        markup(&mut w, locked, "Door", &[]);
+        markup(&mut w, key, "Locked", &[]);
+        w.new_arrow(locked, key);
+        w.new_arrow(open, locked);
+        hoist(&mut w, target, &[ open, locked, key ]);
+
+        let found = find_all(&w, pattern, target);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0][&door], open);
+        assert_eq!(find_one(&w, pattern, target).map(|m| m[&door]), Some(open));
+
+        // hoisting the pattern once the condition's arrow exists pulls the
+        // arrow in too, but it still only belongs to the condition
+        let late = w.new_knot();
+        hoist(&mut w, late, &[ door ]);
+        forbid(&mut w, late, nac);
+        assert_eq!(find_all(&w, late, target), found);
+
+        // replace only rewrites the match the condition lets through
+        let goal = w.new_knot();
+        let opened = w.new_knot();
+        annotate(&mut w, opened, "Identity", &[ DataValue::Entity(door) ]);
+        markup(&mut w, opened, "Door", &[]);
+        hoist(&mut w, goal, &[ opened ]);
+        assert!(replace(&mut w, pattern, goal, target).is_ok());
+        assert!(w.is_valid(locked) && w.is_valid(key));
+
+        w.delete_cascade(open);
+        assert!(find_one(&w, pattern, target).is_none());
+
+        // a condition still applies when homomorphic matching sends the
+        // pattern entities it shares to the same target entity
+        let pattern = w.new_knot();
+        let [ a, b ] = [ 0; 2 ].map(|_| w.new_knot());
+        hoist(&mut w, pattern, &[ a, b ]);
+        let nac = w.new_knot();
+        let n = w.new_knot();
+        require_component(&mut w, n, "Locked");
+        let (an, bn) = (w.new_arrow(a, n), w.new_arrow(b, n));
+        hoist(&mut w, nac, &[ a, b, n, an, bn ]);
+        forbid(&mut w, pattern, nac);
+
+        let target = w.new_knot();
+        let x = w.new_knot();
+        let key = w.new_knot();
+        markup(&mut w, key, "Locked", &[]);
+        w.new_arrow(x, key);
+        hoist(&mut w, target, &[ x, key ]);
+        let homomorphic = MatchOptions { injective: false, induced: false };
+        let found = find_all_with(&w, pattern, target, homomorphic);
+        assert_eq!(found.len(), 3);
+        assert!(!found.iter().any(|m| m[&a] == x && m[&b] == x));
+    }
+
+    #[test]
+    fn match_options_choose_semantics() {
+        let mut w: Weave = Weave::new();
+
+        // a path of two arrows
+        let [ a, b, c ] = [ 0; 3 ].map(|_| w.new_knot());
+        let ab = w.new_arrow(a, b);
+        let bc = w.new_arrow(b, c);
+        let pattern = w.new_knot();
+        hoist(&mut w, pattern, &[ a, b, c ]);
+
+        // a triangle, and a knot with an arrow to itself
+        let target = w.new_knot();
+        let [ x, y, z ] = [ 0; 3 ].map(|_| w.new_knot());
+        w.new_arrow(x, y);
+        w.new_arrow(y, z);
+        w.new_arrow(x, z);
+        hoist(&mut w, target, &[ x, y, z ]);
+        let looped = w.new_knot();
+        let l = w.new_knot();
+        let ll = w.new_arrow(l, l);
+        hoist(&mut w, looped, &[ l ]);
+
+        let injective = MatchOptions::default();
+        let induced = MatchOptions { induced: true, ..injective };
+        let homomorphic = MatchOptions { injective: false, induced: false };
+        assert_eq!(find_all_with(&w, pattern, target, injective).len(), 1);
+        assert!(find_one_with(&w, pattern, target, induced).is_none());
+        assert!(find_one(&w, pattern, looped).is_none());
+        let folded = find_one_with(&w, pattern, looped, homomorphic).unwrap();
+        assert_eq!((folded[&a], folded[&b], folded[&c]), (l, l, l));
+        assert_eq!((folded[&ab], folded[&bc]), (ll, ll));
+        assert_eq!(find_all_with(&w, pattern, looped, homomorphic), vec![ folded ]);
+        assert_eq!(find_all(&w, pattern, target), find_all_with(&w, pattern, target, injective));
+    }
+
+    #[test]
+    fn matcher_agrees_with_exhaustive_search() {
+        let mut w: Weave = Weave::new();
+        let target = w.new_knot();
+        let knots = (0..5).map(|_| w.new_knot()).collect::<Vec<_>>();
+        for (s, t) in [ (0, 1), (1, 2), (2, 0), (1, 3), (3, 4), (4, 4), (0, 2) ] {
+            w.new_arrow(knots[s], knots[t]);
+        }
+        markup(&mut w, knots[2], "Marked", &[]);
+        markup(&mut w, knots[3], "Marked", &[]);
+        hoist(&mut w, target, &knots);
+
+        let pattern = w.new_knot();
+        let [ a, b, c ] = [ 0; 3 ].map(|_| w.new_knot());
+        w.new_arrow(a, b);
+        w.new_arrow(b, c);
+        require_component(&mut w, c, "Marked");
+        hoist(&mut w, pattern, &[ a, b, c ]);
+
+        let sorted = |mut found: Vec<HashMap<EntityId, EntityId>>| {
+            let mut found = found.drain(..)
+                .map(|m| { let mut m = m.into_iter().collect::<Vec<_>>(); m.sort(); m })
+                .collect::<Vec<_>>();
+            found.sort();
+            found
+        };
+        assert!(!find_all(&w, pattern, target).is_empty());
+        for (injective, induced) in [ (true, false), (true, true), (false, false), (false, true) ] {
+            let options = MatchOptions { injective, induced };
+            let found = sorted(find_all_with(&w, pattern, target, options));
+            assert_eq!(found, sorted(find_all_exhaustive(&w, pattern, target, options)));
+        }
+
+        let empty = w.new_knot();
+        assert_eq!(find_all_exhaustive(&w, empty, target, MatchOptions::default()), vec![ HashMap::new() ]);
+        assert_eq!(find_all_with(&w, empty, target, MatchOptions::default()), vec![ HashMap::new() ]);
+    }
+
+    #[test]
+    fn matches_stream_and_stop_early() {
+        let mut w: Weave = Weave::new();
+        let target = w.new_knot();
+        let knots = (0..6).map(|_| w.new_knot()).collect::<Vec<_>>();
+        for i in 0..6 {
+            w.new_arrow(knots[i], knots[(i + 1) % 6]);
+        }
+        hoist(&mut w, target, &knots);
+
+        let pattern = w.new_knot();
+        let [ a, b ] = [ 0; 2 ].map(|_| w.new_knot());
+        w.new_arrow(a, b);
+        hoist(&mut w, pattern, &[ a, b ]);
+
+        let mut all = matches(&w, pattern, target);
+        assert_eq!(all.by_ref().count(), 6);
+        assert_eq!(all.stop_reason(), None);
+        assert_eq!(all.next(), None);
+
+        let mut first = matches(&w, pattern, target).max_count(2);
+        assert_eq!(first.by_ref().count(), 2);
+        assert_eq!(first.stop_reason(), Some(StopReason::Count));
+        assert_eq!(first.has_more(0), None);
+        assert_eq!(first.has_more(100), Some(true));
+        assert_eq!(first.next(), None);
+        let mut exact = matches(&w, pattern, target).max_count(6);
+        assert_eq!(exact.by_ref().count(), 6);
+        assert_eq!(exact.stop_reason(), Some(StopReason::Count));
+        assert_eq!(exact.has_more(100), Some(false));
+
+        let mut peeking = matches(&w, pattern, target);
+        let mut seen = vec![];
+        loop {
+            match peeking.has_more(1) {
+                Some(true) => seen.push(peeking.next().unwrap()),
+                Some(false) => break,
+                None => continue,
+            }
+        }
+        assert_eq!(seen.len(), 6);
+        assert_eq!(peeking.stop_reason(), None);
+        assert_eq!(peeking.steps(), all.steps());
+
+        let mut short = matches(&w, pattern, target).max_steps(1);
+        assert_eq!(short.by_ref().count(), 0);
+        assert_eq!(short.stop_reason(), Some(StopReason::Steps));
+
+        let mut asked = 0;
+        let mut cancelled = matches(&w, pattern, target).cancel_when(|| { asked += 1; asked > 10 });
+        let found = cancelled.by_ref().count();
+        assert!(found > 0 && found < 6);
+        assert_eq!(cancelled.stop_reason(), Some(StopReason::Cancelled));
+        assert_eq!(cancelled.steps(), 11);
+
+        let mut late = matches(&w, pattern, target).time_limit(Duration::ZERO);
+        assert_eq!(late.next(), None);
+        assert_eq!(late.stop_reason(), Some(StopReason::Timeout));
+    }
+
     #[test]
     fn test_replacements() {
         let mut w: Weave = Weave::new();
@@ -1,119 +1,68 @@
-use std::collections::HashSet;
 use crate::core::{EntityId, Weave};
 
-pub fn primary(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h: HashSet<EntityId> = HashSet::new();
-    for i in it {
-        let di = wv.get_dependents(*i)
-            .iter().filter(|&e| wv.is_knot(*e) || wv.is_arrow(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(&di);
-    }
+/*
+    Every collecting move returns its entities sorted by id and without
+    duplicates, no matter the order of its inputs, so results (and
+    whatever is built from them, like rewrites or serialized weaves) are
+    the same from run to run. The exceptions are `to_src` and `to_tgt`,
+    which map their inputs one to one and keep their order.
+ */
+fn sorted(mut ids: Vec<EntityId>) -> Vec<EntityId> {
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
 
-    h.into_iter().collect()
+pub fn primary(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
+    sorted(deps_iter(wv, it.iter().copied()).filter(|&e| wv.is_knot(e) || wv.is_arrow(e)).collect())
 }
 
 pub fn virtuals(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h: HashSet<EntityId> = HashSet::new();
-    for i in it {
-        let di = wv.get_dependents(*i)
-            .iter().filter(|&e| wv.is_mark(*e) || wv.is_tether(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(&di);
-    }
-
-    h.into_iter().collect()
+    sorted(deps_iter(wv, it.iter().copied()).filter(|&e| wv.is_mark(e) || wv.is_tether(e)).collect())
 }
 
 pub fn deps(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h: HashSet<EntityId> = HashSet::new();
-    for i in it {
-        let di = wv.get_dependents(*i);
-        h.extend(&di);
-    }
-
-    let all = h.into_iter().collect();
-    all
+    sorted(deps_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn external_deps(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h: HashSet<EntityId> = HashSet::new();
-    for i in it {
-        let di = wv.get_external_dependents(*i);
-        h.extend(&di);
-    }
-
-    let all = h.into_iter().collect();
-    all
+    sorted(external_deps_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn arrows(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    for i in it {
-        let v = &wv.get_dependents(*i)
-            .iter().filter(|&e| *e != *i && wv.is_arrow(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(v);
-    }
-    h.into_iter().collect()
+    sorted(arrows_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn arrows_between(wv: &Weave, from: &[EntityId], to: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    h.extend(arrows_out(wv, from));
     let in_arrows = arrows_in(wv, to);
-    h.retain(|e| in_arrows.contains(e));
-    h.into_iter().collect()
+    arrows_out(wv, from).into_iter()
+        .filter(|e| in_arrows.binary_search(e).is_ok())
+        .collect()
 }
 
 pub fn arrows_in(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    for i in it {
-        let v = &wv.get_dependents_for_target(*i)
-            .iter().filter(|&e| *e != *i && wv.is_arrow(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(v);
-    }
-    h.into_iter().collect()
+    sorted(arrows_in_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn marks(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    for i in it {
-        let v = &wv.get_dependents_for_target(*i)
-            .iter().filter(|&e| *e != *i && wv.is_mark(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(v);
-    }
-    h.into_iter().collect()
+    sorted(marks_iter(wv, it.iter().copied()).collect())
 }
 
+/*
+    The oldest (lowest id) tether of the entity
+ */
 pub fn tether(wv: &Weave, id: EntityId) -> Option<EntityId> {
     wv.get_dependents_for_source(id)
-        .iter().filter(|e| **e != id && wv.is_tether(**e))
-        .cloned().collect::<Vec<_>>().first().copied()
+        .iter().find(|e| **e != id && wv.is_tether(**e))
+        .copied()
 }
 
 pub fn tethers(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    for i in it {
-        let v = &wv.get_dependents_for_source(*i)
-            .iter().filter(|&e| *e != *i && wv.is_tether(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(v);
-    }
-    h.into_iter().collect()
+    sorted(tethers_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn arrows_out(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
-    let mut h = HashSet::new();
-    for i in it {
-        let v = &wv.get_dependents_for_source(*i)
-            .iter().filter(|&e| *e != *i && wv.is_arrow(*e))
-            .cloned().collect::<Vec<_>>();
-        h.extend(v);
-    }
-    h.into_iter().collect()
+    sorted(arrows_out_iter(wv, it.iter().copied()).collect())
 }
 
 pub fn to_src(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
@@ -162,7 +111,7 @@ pub fn next_n(wv: &Weave, it: &[EntityId]) -> Vec<EntityId> {
 //              to_tgt
 //                  to_tgt
 pub fn down(wv: &Weave, it: EntityId) -> Vec<EntityId> {
-    to_tgt(wv, &to_tgt(wv, &arrows_out(wv, &tethers(wv, &[it]))))
+    sorted(down_iter(wv, it).collect())
 }
 
 pub fn down_half(wv: &Weave, it: EntityId) -> Option<EntityId> {
@@ -186,11 +135,7 @@ pub fn down_n(wv: &Weave, its: &[EntityId]) -> Vec<EntityId> {
 //        to_src
 //  to_src
 pub fn up(wv: &Weave, it: EntityId) -> Vec<EntityId> {
-    let marks = marks(wv, &[it]);
-    let arrows = arrows_in(wv, &marks);
-    let tethers = to_src(wv, &arrows);
-    let entities = to_src(wv, &tethers);
-    entities
+    sorted(up_iter(wv, it).collect())
 }
 
 pub fn up_n(wv: &Weave, its: &[EntityId]) -> Vec<EntityId> {
@@ -198,4 +143,76 @@ pub fn up_n(wv: &Weave, its: &[EntityId]) -> Vec<EntityId> {
     c.sort();
     c.dedup();
     c
-}
\ No newline at end of file
+}
+
+/*
+    Lazy versions of the moves above. They walk the weave's adjacency in
+    place instead of collecting, so chaining them doesn't allocate; in
+    exchange nothing is sorted or deduplicated. Entities come out per
+    input, each input's in id order, and one reached from several inputs
+    comes out once for each of them.
+ */
+pub fn deps_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.dependents(i))
+}
+
+pub fn external_deps_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.dependents(i).filter(move |e| *e != i))
+}
+
+pub fn arrows_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.dependents(i).filter(move |e| *e != i && wv.is_arrow(*e)))
+}
+
+pub fn arrows_in_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.get_dependents_for_target(i).iter().copied()
+        .filter(move |e| *e != i && wv.is_arrow(*e)))
+}
+
+pub fn arrows_out_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.get_dependents_for_source(i).iter().copied()
+        .filter(move |e| *e != i && wv.is_arrow(*e)))
+}
+
+pub fn marks_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.get_dependents_for_target(i).iter().copied()
+        .filter(move |e| *e != i && wv.is_mark(*e)))
+}
+
+pub fn tethers_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().flat_map(move |i| wv.get_dependents_for_source(i).iter().copied()
+        .filter(move |e| *e != i && wv.is_tether(*e)))
+}
+
+pub fn to_src_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().map(move |e| wv.src(e))
+}
+
+pub fn to_tgt_iter<'w, I>(wv: &'w Weave, it: I) -> impl Iterator<Item = EntityId> + 'w
+    where I: IntoIterator<Item = EntityId>, I::IntoIter: 'w {
+    it.into_iter().map(move |e| wv.tgt(e))
+}
+
+pub fn prev_iter(wv: &Weave, it: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    to_src_iter(wv, external_deps_iter(wv, [it])).filter(move |e| *e != it)
+}
+
+pub fn next_iter(wv: &Weave, it: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    to_tgt_iter(wv, external_deps_iter(wv, [it])).filter(move |e| *e != it)
+}
+
+pub fn down_iter(wv: &Weave, it: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    to_tgt_iter(wv, to_tgt_iter(wv, arrows_out_iter(wv, tethers_iter(wv, [it]))))
+}
+
+pub fn up_iter(wv: &Weave, it: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+    to_src_iter(wv, to_src_iter(wv, arrows_in_iter(wv, marks_iter(wv, [it]))))
+}
@@ -0,0 +1,74 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/*
+    Implements `wv::component::Component` for a struct with named fields.
+    The datatype is named after the struct unless overridden with
+    `#[component(name = "...")]`; every field type has to implement
+    `wv::component::FieldValue`.
+ */
+#[proc_macro_derive(Component, attributes(component))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn datatype_name(input: &DeriveInput) -> syn::Result<String> {
+    let mut name = input.ident.to_string();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("component")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(&input.ident, "Component needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(&input.ident, "Component can only be derived for structs")),
+    };
+
+    let ident = &input.ident;
+    let name = datatype_name(input)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let idents = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect::<Vec<_>>();
+    let names = idents.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+    let types = fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+
+    Ok(quote! {
+        impl #impl_generics ::wv::component::Component for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn fields() -> ::std::vec::Vec<::wv::core::DataField> {
+                ::std::vec![
+                    #( ::wv::core::DataField::new(#names, <#types as ::wv::component::FieldValue>::datatype()) ),*
+                ]
+            }
+
+            fn to_values(&self) -> ::std::vec::Vec<::wv::core::DataValue> {
+                ::std::vec![ #( ::wv::component::FieldValue::to_value(&self.#idents) ),* ]
+            }
+
+            fn from_values(values: &[::wv::core::DataValue]) -> ::std::option::Option<Self> {
+                let mut values = values.iter();
+                ::std::option::Option::Some(#ident {
+                    #( #idents: ::wv::component::FieldValue::from_value(values.next()?)?, )*
+                })
+            }
+        }
+    })
+}
@@ -24,11 +24,11 @@ int main()
     std::cout << wv.IsNil(c) << std::endl;
 
     wv.DefineData("Test", {
-        { "i", Datatype::Int },
-        { "b", Datatype::Bool },
-        { "s", Datatype::String },
-        { "f", Datatype::Float },
-        { "z", Datatype::String }
+        { "i", { WvDatatypeKind::Int } },
+        { "b", { WvDatatypeKind::Bool } },
+        { "s", { WvDatatypeKind::String } },
+        { "f", { WvDatatypeKind::Float } },
+        { "z", { WvDatatypeKind::String } }
     });
 
     std::cout << BOOL(wv.HasComponent(c, "Test")) << std::endl;
@@ -5,47 +5,49 @@
 #include <map>
 #include <memory>
 #include <optional>
+#include <string>
+#include <utility>
 #include <vector>
 
 using EntityId = size_t;
 using DataId = uint64_t;
 
 struct DataFieldValue {
-	Datatype datatype;
+	WvDatatypeKind datatype;
 	const void* value;
 };
 
 struct DataComponent {
-	std::map<std::string_view, DataFieldValue> values;
+	std::map<std::string, DataFieldValue> values;
 
 	int32_t GetInt(std::string_view name)
 	{
-		DataFieldValue& value = values[name];
-		assert(value.datatype == Datatype::Int);
+		DataFieldValue& value = values[std::string(name)];
+		assert(value.datatype == WvDatatypeKind::Int);
 
 		return *(int32_t*)value.value;
 	}
 
 	float GetFloat(std::string_view name)
 	{
-		DataFieldValue& value = values[name];
-		assert(value.datatype == Datatype::Float);
+		DataFieldValue& value = values[std::string(name)];
+		assert(value.datatype == WvDatatypeKind::Float);
 
 		return *(float*)value.value;
 	}
 
 	bool GetBool(std::string_view name)
 	{
-		DataFieldValue& value = values[name];
-		assert(value.datatype == Datatype::Bool);
+		DataFieldValue& value = values[std::string(name)];
+		assert(value.datatype == WvDatatypeKind::Bool);
 
 		return *(bool*)value.value;
 	}
 
 	std::string_view GetString(std::string_view name)
 	{
-		DataFieldValue& value = values[name];
-		assert(value.datatype == Datatype::String);
+		DataFieldValue& value = values[std::string(name)];
+		assert(value.datatype == WvDatatypeKind::String);
 
 		return (const char*)value.value;
 	}
@@ -219,6 +221,15 @@ namespace weave
 			result.assign(arr.ptr, arr.ptr + arr.len);
 			return result;
 		}
+
+		std::optional<std::vector<EntityId>> Query(const std::vector<EntityId>& it, const std::string& path) {
+			auto arr = wv_query(m_Weave, it.size(), it.data(), path.c_str());
+			if (arr.ptr == nullptr) return std::nullopt;
+
+			std::vector<EntityId> result;
+			result.assign(arr.ptr, arr.ptr + arr.len);
+			return result;
+		}
 	};
 
 	class WeaveLibrarySearch : IWeaveLibrary
@@ -239,11 +250,11 @@ namespace weave
 			std::vector<SearchResult> entries;
 		};
 
-		std::optional<SearchResult> FindOne(EntityId pattern, EntityId target)
+		std::optional<SearchResult> FindOne(EntityId pattern, EntityId target, WvMatchOptions options = { true, false })
 		{
 			size_t count{ 0 };
 			size_t size{ 0 };
-			auto arr = wv_search__find_one(m_Weave, pattern, target, &size, &count);
+			auto arr = wv_search__find_one_with(m_Weave, pattern, target, options, &size, &count);
 			if (count == 0) return std::nullopt;
 
 			std::vector<EntityId> unwrapped;
@@ -260,11 +271,11 @@ namespace weave
 			return std::optional(result);
 		}
 
-		std::optional<SearchResults> FindAll(EntityId pattern, EntityId target)
+		std::optional<SearchResults> FindAll(EntityId pattern, EntityId target, WvMatchOptions options = { true, false })
 		{
 			size_t count{ 0 };
 			size_t size{ 0 };
-			auto arr = wv_search__find_all(m_Weave, pattern, target, &size, &count);
+			auto arr = wv_search__find_all_with(m_Weave, pattern, target, options, &size, &count);
 			if (count == 0) return std::nullopt;
 
 			std::vector<EntityId> unwrapped;
@@ -288,6 +299,56 @@ namespace weave
 
 			return std::optional(results);
 		}
+
+		class Cursor
+		{
+		public:
+			explicit Cursor(WvSearchCursor* cursor) : m_Cursor(cursor)
+			{
+			}
+
+			Cursor(const Cursor&) = delete;
+			Cursor& operator=(const Cursor&) = delete;
+
+			Cursor(Cursor&& other) noexcept : m_Cursor(std::exchange(other.m_Cursor, nullptr))
+			{
+			}
+
+			~Cursor()
+			{
+				if (m_Cursor) wv_search__end(m_Cursor);
+			}
+
+			std::optional<SearchResult> Next()
+			{
+				WvEntityArray arr{ 0, nullptr };
+				if (!wv_search__next(m_Cursor, &arr)) return std::nullopt;
+
+				SearchResult result;
+				result.count = arr.len / 2;
+				for (size_t i = 0; i < arr.len; i += 2)
+				{
+					result.source.push_back(arr.ptr[i]);
+					result.target.push_back(arr.ptr[i + 1]);
+				}
+
+				return std::optional(result);
+			}
+
+			WvStopReason StopReason() const
+			{
+				return wv_search__stop_reason(m_Cursor);
+			}
+
+		private:
+			WvSearchCursor* m_Cursor;
+		};
+
+		Cursor Begin(EntityId pattern, EntityId target, WvMatchOptions options = { true, false }, WvMatchLimits limits = { 0, 0, 0 },
+		             bool (*cancel)(void*) = nullptr, void* userData = nullptr)
+		{
+			return Cursor(wv_search__begin(m_Weave, pattern, target, options, limits, cancel, userData));
+		}
 	};
 
 	class Weave
@@ -398,11 +459,17 @@ namespace weave
 			return wv_get_data_field_count(GetWeave(), name.data());
 		}
 
+		// the field has to be handed back to FreeDataField
 		WvDataField GetDataField(std::string_view name, size_t index)
 		{
 			return wv_get_data_field(GetWeave(), name.data(), index);
 		}
 
+		void FreeDataField(WvDataField field)
+		{
+			wv_free_data_field(field);
+		}
+
 		const void* GetComponentField(EntityId id, std::string_view name, size_t index)
 		{
 			return wv_get_component_field(GetWeave(), id, name.data(), index);
@@ -431,9 +498,10 @@ namespace weave
 			{
 				WvDataField data = GetDataField(name, i);
 				DataFieldValue field{};
-				field.datatype = data.datatype;
+				field.datatype = data.datatype.kind;
 				field.value = GetComponentField(id, name, i);
 				result.values[data.name] = field;
+				FreeDataField(data);
 			}
 
 			return result;